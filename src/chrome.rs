@@ -1,255 +1,2801 @@
-use std::time::Duration;
-use std::{ops::Deref, sync::Arc};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{str::FromStr, sync::Arc};
 
+use base64::Engine;
 use chromiumoxide::{
-    Page,
+    Command, Handler, Method, Page,
     browser::{Browser, BrowserConfig},
-    cdp::browser_protocol::page::PrintToPdfParams,
-    page::MediaTypeParams,
+    cdp::browser_protocol::{
+        browser::{BrowserContextId, SetDownloadBehaviorBehavior, SetDownloadBehaviorParams},
+        dom::Rgba,
+        emulation::{
+            ClearDeviceMetricsOverrideParams, MediaFeature,
+            SetDefaultBackgroundColorOverrideParams, SetDeviceMetricsOverrideParams,
+            SetEmulatedMediaParams, SetLocaleOverrideParams, SetScriptExecutionDisabledParams,
+            SetUserAgentOverrideParams,
+        },
+        network::{
+            EventLoadingFailed, EventRequestWillBeSent, EventResponseReceived, Headers,
+            ResourceType, SetBlockedUrLsParams, SetExtraHttpHeadersParams,
+        },
+        page::{CaptureScreenshotFormat, PrintToPdfParams, PrintToPdfReturns},
+        target::{CreateBrowserContextParams, CreateTargetParams},
+    },
+    error::CdpError,
+    handler::HandlerConfig,
+    page::ScreenshotParams,
+    types::MethodId,
 };
 use color_eyre::eyre::{Context, Result, eyre};
 use futures::StreamExt;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
+use tracing::Instrument;
 
-use crate::wait::{setup_custom_event_wait, wait_for_network_idle};
-use crate::worker::{Task, WorkerPool};
+use crate::devices::{self, Device};
+use crate::error::{PdfError, ValidationError};
+use crate::header_footer::{
+    DEFAULT_TEMPLATE_MARGIN, EMPTY_TEMPLATE, PageNumberSpec, TRIAL_MARGIN, TemplateFit,
+    with_trial_notice,
+};
+use crate::markdown;
+use crate::metrics::{ConsoleErrors, ResourceErrors, ResourceUsage};
+use crate::output::{OutputFormat, PdfMeta, PdfOutput, PrintInfo};
+use crate::postprocess::{
+    Attachment, CompressionLevel, DocumentInfo, HeaderFooterPages, Optimizer, PDF_VERSIONS,
+    PostProcess, compress, is_pdf_date, looks_blank, merge,
+};
+use crate::progress::{Progress, RenderEvent};
+use crate::wait::{
+    NetworkIdleKind, install_dom_mutation_binding, install_render_binding, setup_custom_event_wait,
+    wait_for_animations, wait_for_dom_stable, wait_for_expression, wait_for_network_idle,
+    wait_for_selector,
+};
+use crate::worker::{Backpressure, PoolStats, Task, WorkerPool};
+
+/// Paper formats accepted in `format`, with their width and height in inches
+pub const PAPER_FORMATS: &[(&str, f64, f64)] = &[
+    ("LETTER", 8.5, 11.0),
+    ("LEGAL", 8.5, 14.0),
+    ("TABLOID", 11.0, 17.0),
+    ("LEDGER", 17.0, 11.0),
+    ("A0", 33.1, 46.8),
+    ("A1", 23.4, 33.1),
+    ("A2", 16.5, 23.4),
+    ("A3", 11.7, 16.5),
+    ("A4", 8.27, 11.7),
+    ("A5", 5.83, 8.27),
+    ("A6", 4.13, 5.83),
+];
+
+fn format_to_inches(format: &str) -> Option<(f64, f64)> {
+    PAPER_FORMATS
+        .iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(format))
+        .map(|&(_, width, height)| (width, height))
+}
+
+/// Paper size for `format`, defaulting to A4
+fn paper_size(format: Option<&str>) -> (f64, f64) {
+    format.and_then(format_to_inches).unwrap_or((8.27, 11.7))
+}
+
+/// Smallest `width` or `height` in inches, below which Chrome can't fit a line of text
+const MIN_DIMENSION_INCHES: f64 = 0.1;
+
+/// Largest `width` or `height` in inches, the most a PDF page can be (14400pt)
+const MAX_DIMENSION_INCHES: f64 = 200.0;
+
+/// Length in inches of a `width` or `height` such as `210mm`, `21cm`, `8.5in`, `816px`
+/// (at 96 per inch) or `612pt` (at 72); a bare number is in inches
+pub fn parse_dimension(s: &str) -> Result<f64> {
+    let s = s.trim();
+    let (number, per_inch) = [
+        ("mm", 25.4),
+        ("cm", 2.54),
+        ("in", 1.0),
+        ("px", 96.0),
+        ("pt", 72.0),
+    ]
+    .into_iter()
+    .find_map(|(unit, per_inch)| Some((s.strip_suffix(unit)?, per_inch)))
+    .unwrap_or((s, 1.0));
+    let value: f64 = number.trim().parse().map_err(|_| {
+        eyre!(
+            "invalid dimension `{}`, expected a number with mm, cm, in, px, pt or no unit",
+            s
+        )
+    })?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err(eyre!("dimension `{}` must be above zero", s));
+    }
+    let inches = value / per_inch;
+    if !(MIN_DIMENSION_INCHES..=MAX_DIMENSION_INCHES).contains(&inches) {
+        return Err(eyre!(
+            "dimension `{}` must be between {}in and {}in",
+            s,
+            MIN_DIMENSION_INCHES,
+            MAX_DIMENSION_INCHES
+        ));
+    }
+    Ok(inches)
+}
+
+/// Parse a CSS color: `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb()`, `rgba()`,
+/// `white`, `black` or `transparent`
+fn parse_color(color: &str) -> Option<Rgba> {
+    let color = color.trim().to_ascii_lowercase();
+    let rgba = |r, g, b, a: f64| Rgba {
+        r,
+        g,
+        b,
+        a: Some(a),
+    };
+
+    match color.as_str() {
+        "white" => return Some(rgba(255, 255, 255, 1.0)),
+        "black" => return Some(rgba(0, 0, 0, 1.0)),
+        "transparent" => return Some(rgba(0, 0, 0, 0.0)),
+        _ => {}
+    }
+
+    if let Some(hex) = color.strip_prefix('#') {
+        let digits: Vec<i64> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(i64::from))
+            .collect::<Option<_>>()?;
+        let alpha = |a: i64| a as f64 / 255.0;
+        return match digits[..] {
+            [r, g, b] => Some(rgba(r * 17, g * 17, b * 17, 1.0)),
+            [r, g, b, a] => Some(rgba(r * 17, g * 17, b * 17, alpha(a * 17))),
+            [r1, r2, g1, g2, b1, b2] => Some(rgba(r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, 1.0)),
+            [r1, r2, g1, g2, b1, b2, a1, a2] => Some(rgba(
+                r1 * 16 + r2,
+                g1 * 16 + g2,
+                b1 * 16 + b2,
+                alpha(a1 * 16 + a2),
+            )),
+            _ => None,
+        };
+    }
+
+    let args = color
+        .strip_prefix("rgba(")
+        .or_else(|| color.strip_prefix("rgb("))?
+        .strip_suffix(')')?;
+    let parts: Vec<&str> = args
+        .split([',', ' ', '/'])
+        .filter(|part| !part.is_empty())
+        .collect();
+    let channel = |part: &str| part.parse::<i64>().ok().filter(|c| (0..=255).contains(c));
+    let (r, g, b) = (
+        channel(parts.first()?)?,
+        channel(parts.get(1)?)?,
+        channel(parts.get(2)?)?,
+    );
+    let a = match parts.get(3..)? {
+        [] => 1.0,
+        [a] => a.parse::<f64>().ok().filter(|a| (0.0..=1.0).contains(a))?,
+        _ => return None,
+    };
+    Some(rgba(r, g, b, a))
+}
+
+/// Page margins in inches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Margins {
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+    pub left: f64,
+}
+
+impl Margins {
+    pub const fn uniform(inches: f64) -> Self {
+        Self {
+            top: inches,
+            right: inches,
+            bottom: inches,
+            left: inches,
+        }
+    }
+}
+
+/// Built-in margins for payloads that leave them out, by format as `format_to_inches` knows it
+pub fn default_format_margins() -> HashMap<String, Margins> {
+    [
+        ("LETTER", 0.5),
+        ("LEGAL", 0.5),
+        ("TABLOID", 0.6),
+        ("LEDGER", 0.6),
+        ("A0", 0.8),
+        ("A1", 0.7),
+        ("A2", 0.6),
+        ("A3", 0.5),
+        ("A4", 0.4),
+        ("A5", 0.3),
+        ("A6", 0.2),
+    ]
+    .into_iter()
+    .map(|(format, inches)| (format.to_string(), Margins::uniform(inches)))
+    .collect()
+}
+
+/// Check a Chrome page range such as `1-5, 8, 11-13`
+fn is_valid_page_range(ranges: &str) -> bool {
+    let is_page = |s: &str| s.parse::<u32>().is_ok_and(|n| n > 0);
+
+    ranges.split(',').all(|range| {
+        let range = range.trim();
+        match range.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (from.trim(), to.trim());
+                (from.is_empty() || is_page(from))
+                    && (to.is_empty() || is_page(to))
+                    && !(from.is_empty() && to.is_empty())
+            }
+            None => is_page(range),
+        }
+    })
+}
+
+/// How much a payload's HTML is trusted.
+///
+/// Untrusted renders get these guarantees:
+/// - page scripts never run; pdfan's own CDP commands still work
+/// - every http(s), ws(s), ftp and file request is blocked, so the document can neither
+///   load remote resources nor read local files; inline and `data:` content still renders
+/// - the page lives in its own browser context, created for the render and disposed
+///   afterwards, so it shares no cookies, storage or cache with other renders
+///
+/// They don't protect against bugs in Chrome itself; run it sandboxed for that.
+/// Only `html` can be rendered untrusted, since navigating to a URL needs the network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    #[default]
+    Trusted,
+    Untrusted,
+}
+
+impl FromStr for RenderMode {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "trusted" => Ok(Self::Trusted),
+            "untrusted" => Ok(Self::Untrusted),
+            _ => Err(eyre!(
+                "unknown render mode `{}`, expected trusted or untrusted",
+                s
+            )),
+        }
+    }
+}
+
+/// ICU locale for the first language of an `Accept-Language` value, `fr-FR,fr;q=0.9` -> `fr_FR`
+fn primary_locale(accept_language: &str) -> Option<String> {
+    let first = accept_language.split(',').next()?.split(';').next()?.trim();
+    (!first.is_empty() && first != "*").then(|| first.replace('-', "_"))
+}
+
+/// Most pages a `url_template` render may fetch
+const MAX_TEMPLATE_PAGES: u32 = 50;
+
+/// Default for `domQuietPeriodMs`
+const DEFAULT_DOM_QUIET_PERIOD_MS: u64 = 500;
+
+/// How much wider than a portrait page `autoOrient` content must be to print in landscape;
+/// content between this and the page's width keeps the payload's `landscape`
+const AUTO_ORIENT_OVERFLOW: f64 = 1.1;
+
+/// Media features `mediaFeatures` may emulate, the ones Chrome supports overriding
+pub const MEDIA_FEATURES: &[&str] = &[
+    "color-gamut",
+    "forced-colors",
+    "prefers-color-scheme",
+    "prefers-contrast",
+    "prefers-reduced-data",
+    "prefers-reduced-motion",
+    "prefers-reduced-transparency",
+];
+
+/// Default for `ChromeDriverConfig::max_html_bytes`
+pub const DEFAULT_MAX_HTML_BYTES: usize = 50 * 1024 * 1024;
+
+/// Range of `scale` Chrome can print
+const MIN_SCALE: f64 = 0.1;
+const MAX_SCALE: f64 = 2.0;
+
+/// Server-wide bounds for a payload's `scale`, within what Chrome supports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScalePolicy {
+    pub min: f64,
+    pub max: f64,
+    /// Bring scales outside the bounds to the nearest one, instead of rejecting the payload
+    pub clamp: bool,
+}
+
+impl Default for ScalePolicy {
+    fn default() -> Self {
+        Self {
+            min: MIN_SCALE,
+            max: MAX_SCALE,
+            clamp: false,
+        }
+    }
+}
+
+impl ScalePolicy {
+    /// `scale` within the bounds, clamped or rejected as the policy says
+    fn apply(&self, scale: f64) -> Result<f64, PdfError> {
+        if (self.min..=self.max).contains(&scale) {
+            Ok(scale)
+        } else if self.clamp {
+            Ok(scale.clamp(self.min, self.max))
+        } else {
+            Err(PdfError::Validation(vec![ValidationError::new(
+                "scale",
+                format!(
+                    "must be between {} and {} on this server",
+                    self.min, self.max
+                ),
+            )]))
+        }
+    }
+}
+
+/// Shortens the waits of renders queued while the pool is backed up, so slow pages give
+/// way to the queue; see `ChromeDriverConfig::adaptive_waits`.
+///
+/// With `backlog` the renders queued or waiting to queue per worker when a render is
+/// submitted, each of its waits gets
+///
+/// ```text
+/// max(timeout / (1 + backlog), min(floor, timeout))
+/// ```
+///
+/// | backlog per worker | share of the timeout |
+/// |--------------------|----------------------|
+/// | 0                  | all of it            |
+/// | 1                  | 1/2                  |
+/// | 3                  | 1/4                  |
+/// | 9                  | 1/10                 |
+///
+/// Waits without a timeout of their own are cut from what is left of the deadline instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveWaits {
+    /// No wait is cut below this, nor below its own timeout when that is shorter
+    pub floor: Duration,
+}
+
+impl AdaptiveWaits {
+    /// Budget for a render queued with the pool at `stats`
+    fn budget(&self, stats: &PoolStats) -> WaitBudget {
+        let backlog = (stats.queued + stats.waiting) as f64 / stats.workers.max(1) as f64;
+        WaitBudget {
+            share: 1.0 / (1.0 + backlog),
+            floor: self.floor,
+        }
+    }
+}
+
+/// How much of each wait's timeout a render gets
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct WaitBudget {
+    share: f64,
+    floor: Duration,
+}
+
+impl WaitBudget {
+    const FULL: Self = Self {
+        share: 1.0,
+        floor: Duration::ZERO,
+    };
+
+    fn apply(&self, timeout: Duration) -> Duration {
+        timeout.mul_f64(self.share).max(self.floor.min(timeout))
+    }
+}
+
+/// Rejects payloads whose estimated render time is above `budget_ms` before they queue,
+/// keeping predictably slow work off the pool; see `ChromeDriverConfig::admission`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionPolicy {
+    pub budget_ms: u64,
+    pub costs: RenderCosts,
+}
+
+/// Rough milliseconds each payload feature adds to a render, summed into an estimate.
+///
+/// Waits with a timeout of their own cost at most that timeout. The defaults are a
+/// starting point, to be tuned to what renders take on the deployment.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderCosts {
+    /// Every render
+    pub base_ms: u64,
+    /// Each URL loaded, one per page for `urlTemplate`
+    pub url_ms: u64,
+    pub login_ms: u64,
+    /// The waits below count once per document loaded
+    pub wait_for_event_ms: u64,
+    /// `waitForResources: true`, for the network to go fully idle
+    pub network_idle_ms: u64,
+    /// `waitForResources: false`, for at most two open connections
+    pub network_almost_idle_ms: u64,
+    pub wait_for_expression_ms: u64,
+    pub wait_for_animations_ms: u64,
+    pub wait_for_dom_stable_ms: u64,
+    /// Each page `printRange` asks for; open-ended ranges count nothing, being unknown
+    pub per_page_ms: u64,
+}
+
+impl Default for RenderCosts {
+    fn default() -> Self {
+        Self {
+            base_ms: 500,
+            url_ms: 1000,
+            login_ms: 2000,
+            wait_for_event_ms: 3000,
+            network_idle_ms: 2000,
+            network_almost_idle_ms: 1000,
+            wait_for_expression_ms: 1000,
+            wait_for_animations_ms: 1000,
+            wait_for_dom_stable_ms: 1000,
+            per_page_ms: 20,
+        }
+    }
+}
+
+impl AdmissionPolicy {
+    fn check(&self, payload: &ChromeDriverPdfPayload) -> Result<(), PdfError> {
+        let (estimate, features) = payload.estimated_duration(&self.costs);
+        let budget = Duration::from_millis(self.budget_ms);
+        if estimate <= budget {
+            return Ok(());
+        }
+        Err(PdfError::Validation(vec![ValidationError::new(
+            "payload",
+            format!(
+                "estimated to take about {:?} ({}), over this server's budget of {:?}",
+                estimate,
+                features.join(", "),
+                budget
+            ),
+        )]))
+    }
+}
+
+/// Longest `set_content` may take; HTML that loads slower than this is treated as broken
+const SET_CONTENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time kept free for printing when a best-effort render cuts its waits short
+const BEST_EFFORT_PRINT_RESERVE: Duration = Duration::from_secs(5);
+
+/// Extensions of image URLs blocked with `load_images: false`
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "bmp", "ico", "tif", "tiff",
+];
+
+/// URL patterns blocked for untrusted renders
+const UNTRUSTED_BLOCKED_URLS: &[&str] = &[
+    "http://*",
+    "https://*",
+    "ws://*",
+    "wss://*",
+    "ftp://*",
+    "file://*",
+];
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChromeDriverPdfPayload {
+    url: Option<String>,
+    html: Option<String>,
+    /// GitHub-flavored Markdown, converted to HTML and rendered like `html`
+    markdown: Option<String>,
+    /// Stylesheet for `markdown`, one of `markdown::THEMES`
+    theme: Option<String>,
+    title: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    /// Joined with commas into the PDF's keywords
+    keywords: Option<Vec<String>>,
+    /// Application named as the document's creator
+    creator: Option<String>,
+    /// PDF date such as `D:20240131120000Z`, replacing the time of rendering
+    creation_date: Option<String>,
+    media: Option<String>,
+    /// CSS media features to emulate, e.g. `[["prefers-reduced-motion", "reduce"]]`; see
+    /// `MEDIA_FEATURES` for the supported names
+    media_features: Option<Vec<(String, String)>>,
+    format: Option<String>,
+    /// Paper size replacing `format`, given together, e.g. `210mm` by `297mm`; see
+    /// `parse_dimension` for the units
+    width: Option<String>,
+    height: Option<String>,
+    print_range: Option<String>,
+    /// Turn links and any form fields into plain page content, see `PostProcess::flatten`
+    flatten: Option<bool>,
+    /// Zoom of the printed page, between 0.1 and 2 or the server's `ScalePolicy`
+    scale: Option<f64>,
+    /// Print background colors and images, also with `media: "print"`.
+    ///
+    /// Colors are forced with `print-color-adjust: exact`, so Chrome doesn't drop them to
+    /// save ink. Backgrounds the page's own `@media print` rules remove stay removed.
+    #[serde(default)]
+    print_background: bool,
+    /// CSS color behind the document where it has no background of its own, e.g. `white`
+    /// or `#f5f0e6`, instead of leaving those areas transparent
+    background_color: Option<String>,
+    #[serde(default)]
+    landscape: bool,
+    /// Pick the orientation from the content instead of `landscape`: landscape when it is
+    /// clearly wider than a portrait page, like a wide table, portrait when it fits
+    auto_orient: Option<bool>,
+    margin_top: Option<f64>,
+    margin_right: Option<f64>,
+    margin_bottom: Option<f64>,
+    margin_left: Option<f64>,
+    #[serde(default)]
+    display_header_footer: bool,
+    header_template: Option<String>,
+    footer_template: Option<String>,
+    /// What to do when a header or footer looks taller than its margin: `warn` (default),
+    /// `error`, or `grow` the margin to fit
+    template_fit: Option<TemplateFit>,
+    /// Pages that keep the header and footer, e.g. `2-` to leave them off the cover page.
+    ///
+    /// The others are cleared after rendering, by cutting their margins off the printed
+    /// page, so anything else reaching into the margins goes too.
+    header_footer_page_ranges: Option<String>,
+    wait_for_resources: Option<bool>,
+    #[serde(default)]
+    wait_for_event: bool,
+    /// Default budget of each wait below that has no timeout of its own. Waits are always
+    /// cut short by the render's overall timeout; without any of these, that is their only limit.
+    wait_timeout_ms: Option<u64>,
+    wait_for_resources_timeout_ms: Option<u64>,
+    wait_for_event_timeout_ms: Option<u64>,
+    wait_for_expression_timeout_ms: Option<u64>,
+    wait_for_animations_timeout_ms: Option<u64>,
+    wait_for_dom_stable_timeout_ms: Option<u64>,
+    print_selector: Option<String>,
+    /// Start a new page after each element matching this selector, e.g. `.card` to print
+    /// one card per page. Fails when nothing matches.
+    page_per_selector: Option<String>,
+    /// Rewrite the output to this PDF version; Chrome's native version is kept when unset
+    pdf_version: Option<String>,
+    /// `png` or `jpeg` to capture a screenshot of the page instead of printing a PDF
+    output_format: Option<OutputFormat>,
+    /// Capture the whole page rather than the viewport; screenshots only, default true
+    full_page: Option<bool>,
+    /// JPEG quality from 0 to 100
+    quality: Option<u8>,
+    /// Recompress the PDF after rendering: `none` (default) keeps Chrome's output, `default`
+    /// compresses it losslessly, `max` also redeflates Chrome's streams at the highest level
+    compression: Option<CompressionLevel>,
+    /// Generate the header or footer with page numbers instead of a hand-written template
+    page_numbers: Option<PageNumberSpec>,
+    /// Log in through a form before navigating to `url`
+    login: Option<LoginSpec>,
+    /// Log Chrome's memory and layout usage for this render
+    #[serde(default)]
+    log_resource_usage: bool,
+    /// Set to false to render static documents without running their scripts
+    javascript_enabled: Option<bool>,
+    /// Falls back to the driver's default mode
+    render_mode: Option<RenderMode>,
+    /// Print whatever has loaded when waiting runs out of time, instead of failing
+    best_effort: Option<bool>,
+    /// Sent as `Accept-Language` and used as the page locale, e.g. `fr-FR,fr;q=0.9`
+    accept_language: Option<String>,
+    /// JS expression that must become truthy before printing, e.g.
+    /// `document.querySelectorAll('.row').length >= 10`
+    wait_for_expression: Option<String>,
+    /// Wait until running CSS animations and transitions finish, after the other waits
+    wait_for_animations: Option<bool>,
+    /// Wait until the DOM stops changing for `domQuietPeriodMs`, after all other waits
+    wait_for_dom_stable: Option<bool>,
+    /// How long the DOM must go unchanged for `waitForDomStable`, 500ms by default
+    dom_quiet_period_ms: Option<u64>,
+    /// URL with a `{page}` placeholder, rendered for pages 1 to `page_count` and merged
+    url_template: Option<String>,
+    page_count: Option<u32>,
+    /// Return the first error as is, instead of retrying on a fresh page, and report how
+    /// Chrome returned each PDF in `X-Pdfan-Print` (e.g. `inline:52311`)
+    debug: Option<bool>,
+    /// Also return the DOM as it was at print time, scripts' changes included
+    return_html: Option<bool>,
+    /// Set to false to skip fetching images, for faster and smaller text-only PDFs.
+    ///
+    /// Images are recognized by their URL's extension; inline `data:` images still show.
+    /// Blocked images count as finished, so `waitForResources` no longer waits for them.
+    load_images: Option<bool>,
+    /// Extra `Page.printToPDF` parameters for options not exposed above, e.g. after a
+    /// Chrome update. Merged shallowly; fields set through the payload win.
+    extra_pdf_params: Option<serde_json::Value>,
+    /// Shrink the PDF when it is larger than this, failing if it still doesn't fit.
+    ///
+    /// The PDF is first compressed losslessly, then handed to the server's optimizer if
+    /// one is configured, which usually downsamples images and so costs image quality.
+    /// Text-only documents rarely get this large.
+    max_output_bytes: Option<usize>,
+    /// Fail instead of printing when the page calls `console.error` or throws, which often
+    /// means a template is missing data
+    fail_on_console_error: Option<bool>,
+    /// Fail instead of printing when a stylesheet, script, image or other resource fails
+    /// to load or answers with an error status, listing the failed URLs
+    fail_on_resource_error: Option<bool>,
+    /// Only resources whose URL matches one of these (`*` matches anything) count for
+    /// `failOnResourceError`, e.g. `https://cdn.example.com/*`; all do when unset
+    critical_resources: Option<Vec<String>>,
+    /// Report the page count and each page's size in points along with the PDF, as
+    /// `X-Pdfan-Pages` and `X-Pdfan-Page-Sizes` (e.g. `612x792,612x792`)
+    return_meta: Option<bool>,
+    /// Emulate a built-in device such as `iphone-13`, `ipad` or `pixel-7`: its viewport,
+    /// scale factor, user agent and mobile mode. Paper size still comes from `format`,
+    /// the device decides how the page lays itself out.
+    device: Option<String>,
+    /// CSS added once the page has loaded, e.g. to hide navigation or force page breaks.
+    ///
+    /// It goes after the page's own styles, so it wins over rules of equal specificity,
+    /// and applies under whatever `media` is emulated unless wrapped in `@media`.
+    inject_css: Option<String>,
+    /// Check whether the PDF looks blank, e.g. because it printed before content loaded,
+    /// and flag it with `X-Pdfan-Blank: true`
+    detect_blank: Option<bool>,
+    /// Fail instead of returning a PDF that looks blank; implies `detect_blank`
+    fail_on_blank: Option<bool>,
+    /// Render in a browser context of its own, disposed afterwards, so no cookies, storage
+    /// or cache are shared with other renders. Costs some latency; always on when untrusted.
+    isolated: Option<bool>,
+    /// Attach the `html` and this payload to the PDF as embedded files, for provenance.
+    ///
+    /// Grows the PDF by about the compressed size of both; login credentials are left out.
+    embed_source: Option<bool>,
+}
+
+/// Form login performed before rendering, so the target URL loads with the session cookies
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginSpec {
+    url: String,
+    username_selector: String,
+    password_selector: String,
+    username: String,
+    password: String,
+    /// Clicked to submit the form; Enter is pressed in the password field when unset
+    submit_selector: Option<String>,
+    /// Element that only exists once the login succeeded
+    success_selector: String,
+}
+
+impl ChromeDriverPdfPayload {
+    /// Check the payload for problems before it reaches a worker, reporting all of them at once
+    pub fn validate(&self) -> Result<(), PdfError> {
+        let mut errors = Vec::new();
+
+        let sources = [
+            self.url.is_some(),
+            self.html.is_some(),
+            self.markdown.is_some(),
+            self.url_template.is_some(),
+        ];
+        match sources.iter().filter(|given| **given).count() {
+            0 => errors.push(ValidationError::new(
+                "url",
+                "one of url, html, markdown or urlTemplate must be provided",
+            )),
+            1 => {}
+            _ => errors.push(ValidationError::new(
+                "html",
+                "only one of url, html, markdown or urlTemplate may be provided",
+            )),
+        }
+
+        if let Some(theme) = &self.theme {
+            if self.markdown.is_none() {
+                errors.push(ValidationError::new("theme", "only allowed with markdown"));
+            } else if markdown::find_theme(theme).is_none() {
+                let themes: Vec<_> = markdown::THEMES.iter().map(|theme| theme.name).collect();
+                errors.push(ValidationError::new(
+                    "theme",
+                    format!(
+                        "unknown theme `{}`, expected one of {}",
+                        theme,
+                        themes.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(template) = &self.url_template
+            && !template.contains("{page}")
+        {
+            errors.push(ValidationError::new(
+                "urlTemplate",
+                "must contain a `{page}` placeholder",
+            ));
+        }
+        match (self.url_template.is_some(), self.page_count) {
+            (true, None) => errors.push(ValidationError::new(
+                "pageCount",
+                "required with urlTemplate",
+            )),
+            (false, Some(_)) => errors.push(ValidationError::new(
+                "pageCount",
+                "only allowed with urlTemplate",
+            )),
+            (true, Some(count)) if count == 0 || count > MAX_TEMPLATE_PAGES => {
+                errors.push(ValidationError::new(
+                    "pageCount",
+                    format!("must be between 1 and {}", MAX_TEMPLATE_PAGES),
+                ))
+            }
+            _ => {}
+        }
+
+        if let Some(media) = &self.media
+            && !matches!(media.as_str(), "null" | "screen" | "print")
+        {
+            errors.push(ValidationError::new(
+                "media",
+                format!(
+                    "unknown media type `{}`, expected null, screen or print",
+                    media
+                ),
+            ));
+        }
+
+        if let Some(color) = &self.background_color
+            && parse_color(color).is_none()
+        {
+            errors.push(ValidationError::new(
+                "backgroundColor",
+                format!(
+                    "unknown color `{}`, expected a hex color, rgb(), rgba(), white, black or transparent",
+                    color
+                ),
+            ));
+        }
+
+        for (name, _) in self.media_features.iter().flatten() {
+            if !MEDIA_FEATURES.contains(&name.as_str()) {
+                errors.push(ValidationError::new(
+                    "mediaFeatures",
+                    format!(
+                        "unknown media feature `{}`, expected one of {}",
+                        name,
+                        MEDIA_FEATURES.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(format) = &self.format
+            && format_to_inches(format).is_none()
+        {
+            errors.push(ValidationError::new(
+                "format",
+                format!("unknown paper format `{}`", format),
+            ));
+        }
+
+        if self.width.is_some() != self.height.is_some() {
+            errors.push(ValidationError::new(
+                "width",
+                "width and height must be provided together",
+            ));
+        }
+        for (field, dimension) in [("width", &self.width), ("height", &self.height)] {
+            if let Some(Err(e)) = dimension.as_deref().map(parse_dimension) {
+                errors.push(ValidationError::new(field, e.to_string()));
+            }
+        }
+
+        if let Some(ranges) = &self.print_range
+            && !is_valid_page_range(ranges)
+        {
+            errors.push(ValidationError::new(
+                "printRange",
+                format!(
+                    "invalid page range `{}`, expected e.g. `1-5, 8, 11-13`",
+                    ranges
+                ),
+            ));
+        }
+
+        if let Some(ranges) = &self.header_footer_page_ranges {
+            if !is_valid_page_range(ranges) {
+                errors.push(ValidationError::new(
+                    "headerFooterPageRanges",
+                    format!(
+                        "invalid page range `{}`, expected e.g. `2-` or `1, 3-5`",
+                        ranges
+                    ),
+                ));
+            }
+            if self.header_template.is_none()
+                && self.footer_template.is_none()
+                && self.page_numbers.is_none()
+            {
+                errors.push(ValidationError::new(
+                    "headerFooterPageRanges",
+                    "needs a headerTemplate, footerTemplate or pageNumbers",
+                ));
+            }
+        }
+
+        for (field, timeout) in [
+            ("waitTimeoutMs", self.wait_timeout_ms),
+            (
+                "waitForResourcesTimeoutMs",
+                self.wait_for_resources_timeout_ms,
+            ),
+            ("waitForEventTimeoutMs", self.wait_for_event_timeout_ms),
+            (
+                "waitForExpressionTimeoutMs",
+                self.wait_for_expression_timeout_ms,
+            ),
+            (
+                "waitForAnimationsTimeoutMs",
+                self.wait_for_animations_timeout_ms,
+            ),
+            (
+                "waitForDomStableTimeoutMs",
+                self.wait_for_dom_stable_timeout_ms,
+            ),
+        ] {
+            if timeout == Some(0) {
+                errors.push(ValidationError::new(field, "timeout must be positive"));
+            }
+        }
+
+        if let Some(scale) = self.scale
+            && !(MIN_SCALE..=MAX_SCALE).contains(&scale)
+        {
+            errors.push(ValidationError::new(
+                "scale",
+                format!("must be between {} and {}", MIN_SCALE, MAX_SCALE),
+            ));
+        }
+
+        for (field, margin) in [
+            ("marginTop", self.margin_top),
+            ("marginRight", self.margin_right),
+            ("marginBottom", self.margin_bottom),
+            ("marginLeft", self.margin_left),
+        ] {
+            if margin.is_some_and(|m| m < 0.0 || !m.is_finite()) {
+                errors.push(ValidationError::new(
+                    field,
+                    "margin must be a non-negative number",
+                ));
+            }
+        }
+
+        if self
+            .wait_for_expression
+            .as_ref()
+            .is_some_and(|e| e.trim().is_empty())
+        {
+            errors.push(ValidationError::new(
+                "waitForExpression",
+                "expression must not be empty",
+            ));
+        }
+
+        if self
+            .print_selector
+            .as_ref()
+            .is_some_and(|s| s.trim().is_empty())
+        {
+            errors.push(ValidationError::new(
+                "printSelector",
+                "selector must not be empty",
+            ));
+        }
+
+        if self
+            .page_per_selector
+            .as_ref()
+            .is_some_and(|s| s.trim().is_empty())
+        {
+            errors.push(ValidationError::new(
+                "pagePerSelector",
+                "selector must not be empty",
+            ));
+        }
+
+        if let Some(spec) = &self.page_numbers {
+            let (field, template) = if spec.position.is_top() {
+                ("headerTemplate", &self.header_template)
+            } else {
+                ("footerTemplate", &self.footer_template)
+            };
+            if template.is_some() {
+                errors.push(ValidationError::new(
+                    field,
+                    "cannot be combined with pageNumbers at the same position",
+                ));
+            }
+            if spec.font_size.is_some_and(|size| size <= 0.0) {
+                errors.push(ValidationError::new(
+                    "pageNumbers",
+                    "fontSize must be positive",
+                ));
+            }
+        }
+
+        if self.login.is_some() && self.url.is_none() && self.url_template.is_none() {
+            errors.push(ValidationError::new("login", "login requires a url"));
+        }
+
+        if self.is_untrusted() {
+            for (field, given) in [
+                ("url", self.url.is_some()),
+                ("urlTemplate", self.url_template.is_some()),
+                ("login", self.login.is_some()),
+                ("javascriptEnabled", self.javascript_enabled == Some(true)),
+            ] {
+                if given {
+                    errors.push(ValidationError::new(
+                        field,
+                        "not allowed when rendering untrusted content",
+                    ));
+                }
+            }
+        }
+
+        if self.scripts_disabled() && self.wait_for_event {
+            errors.push(ValidationError::new(
+                "waitForEvent",
+                "requires javascriptEnabled, the page can't signal without scripts",
+            ));
+        }
+
+        if self
+            .accept_language
+            .as_ref()
+            .is_some_and(|languages| primary_locale(languages).is_none())
+        {
+            errors.push(ValidationError::new(
+                "acceptLanguage",
+                "expected a language list such as `fr-FR,fr;q=0.9`",
+            ));
+        }
+
+        if self
+            .extra_pdf_params
+            .as_ref()
+            .is_some_and(|params| !params.is_object())
+        {
+            errors.push(ValidationError::new(
+                "extraPdfParams",
+                "must be an object of printToPDF parameters",
+            ));
+        }
+
+        if let Some(name) = &self.device
+            && devices::find(name).is_none()
+        {
+            let known: Vec<&str> = devices::DEVICES.iter().map(|d| d.name).collect();
+            errors.push(ValidationError::new(
+                "device",
+                format!(
+                    "unknown device `{}`, expected one of {}",
+                    name,
+                    known.join(", ")
+                ),
+            ));
+        }
+
+        if let Some(date) = &self.creation_date
+            && !is_pdf_date(date)
+        {
+            errors.push(ValidationError::new(
+                "creationDate",
+                format!(
+                    "invalid date `{}`, expected `D:YYYYMMDDHHmmSS` with an optional offset such as `Z`",
+                    date
+                ),
+            ));
+        }
+
+        if let Some(version) = &self.pdf_version
+            && !PDF_VERSIONS.contains(&version.as_str())
+        {
+            errors.push(ValidationError::new(
+                "pdfVersion",
+                format!(
+                    "unsupported PDF version `{}`, expected one of {}",
+                    version,
+                    PDF_VERSIONS.join(", ")
+                ),
+            ));
+        }
+
+        let format = self.output_format.unwrap_or_default();
+        if format.is_image() {
+            let pdf_only = [
+                ("urlTemplate", self.url_template.is_some()),
+                ("pdfVersion", self.pdf_version.is_some()),
+                ("flatten", self.flatten.is_some()),
+                ("compression", self.compression.is_some()),
+                ("embedSource", self.embed_source.is_some()),
+                (
+                    "headerFooterPageRanges",
+                    self.header_footer_page_ranges.is_some(),
+                ),
+                ("maxOutputBytes", self.max_output_bytes.is_some()),
+                ("returnMeta", self.return_meta.is_some()),
+                ("detectBlank", self.detect_blank.is_some()),
+                ("failOnBlank", self.fail_on_blank.is_some()),
+            ];
+            for (field, _) in pdf_only.into_iter().filter(|(_, given)| *given) {
+                errors.push(ValidationError::new(field, "only allowed with PDF output"));
+            }
+        } else if self.full_page.is_some() {
+            errors.push(ValidationError::new(
+                "fullPage",
+                "only allowed with png or jpeg output",
+            ));
+        }
+        if let Some(quality) = self.quality {
+            if format != OutputFormat::Jpeg {
+                errors.push(ValidationError::new(
+                    "quality",
+                    "only allowed with jpeg output",
+                ));
+            } else if quality > 100 {
+                errors.push(ValidationError::new("quality", "must be between 0 and 100"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PdfError::Validation(errors))
+        }
+    }
+
+    /// URLs of a `url_template` sequence, in page order
+    fn page_urls(&self) -> Option<Vec<String>> {
+        let template = self.url_template.as_ref()?;
+        let count = self.page_count?;
+        Some(
+            (1..=count)
+                .map(|page| template.replace("{page}", &page.to_string()))
+                .collect(),
+        )
+    }
+
+    /// Rough render time by `costs`, with the features that add to it
+    fn estimated_duration(&self, costs: &RenderCosts) -> (Duration, Vec<String>) {
+        let mut total = costs.base_ms;
+        let mut features = Vec::new();
+        let mut add = |feature: String, ms: u64| {
+            if ms > 0 {
+                total = total.saturating_add(ms);
+                features.push(feature);
+            }
+        };
+        // Each wait costs at most its timeout
+        let wait = |ms: u64, timeout_ms: Option<u64>| {
+            self.wait_timeout(timeout_ms)
+                .map_or(ms, |timeout| ms.min(timeout.as_millis() as u64))
+        };
+
+        let documents = match (&self.url, self.page_urls()) {
+            (_, Some(urls)) => {
+                let count = urls.len() as u64;
+                add(
+                    format!("urlTemplate with {} pages", count),
+                    costs.url_ms * count,
+                );
+                count
+            }
+            (Some(_), None) => {
+                add("url".to_string(), costs.url_ms);
+                1
+            }
+            (None, None) => 1,
+        };
+        let navigates = self.url.is_some() || self.url_template.is_some();
+
+        if self.login.is_some() {
+            add("login".to_string(), costs.login_ms);
+        }
+        if navigates && self.wait_for_event {
+            let ms = wait(costs.wait_for_event_ms, self.wait_for_event_timeout_ms);
+            add("waitForEvent".to_string(), ms * documents);
+        }
+        if navigates && !self.wait_for_event {
+            let ms = match self.wait_for_resources {
+                Some(true) => costs.network_idle_ms,
+                Some(false) => costs.network_almost_idle_ms,
+                None => 0,
+            };
+            let ms = wait(ms, self.wait_for_resources_timeout_ms);
+            add("waitForResources".to_string(), ms * documents);
+        }
+        if self.wait_for_expression.is_some() {
+            let ms = wait(
+                costs.wait_for_expression_ms,
+                self.wait_for_expression_timeout_ms,
+            );
+            add("waitForExpression".to_string(), ms * documents);
+        }
+        if self.wait_for_animations == Some(true) {
+            let ms = wait(
+                costs.wait_for_animations_ms,
+                self.wait_for_animations_timeout_ms,
+            );
+            add("waitForAnimations".to_string(), ms * documents);
+        }
+        if self.wait_for_dom_stable == Some(true) {
+            let ms = wait(
+                costs.wait_for_dom_stable_ms,
+                self.wait_for_dom_stable_timeout_ms,
+            );
+            add("waitForDomStable".to_string(), ms * documents);
+        }
+        if let Some(ranges) = &self.print_range {
+            let pages: u64 = ranges
+                .split(',')
+                .filter_map(|range| match range.trim().split_once('-') {
+                    Some((from, to)) => {
+                        let from: u64 = from.trim().parse().unwrap_or(1);
+                        let to: u64 = to.trim().parse().ok()?;
+                        Some(to.saturating_sub(from) + 1)
+                    }
+                    None => range.trim().parse::<u64>().ok().map(|_| 1),
+                })
+                .sum();
+            add(
+                format!("printRange of {} pages", pages),
+                costs.per_page_ms * pages,
+            );
+        }
+
+        (Duration::from_millis(total), features)
+    }
+
+    /// Paper size in inches, from `width` and `height` when both are given, else `format`
+    fn paper_size(&self) -> Result<(f64, f64)> {
+        match (&self.width, &self.height) {
+            (Some(width), Some(height)) => Ok((parse_dimension(width)?, parse_dimension(height)?)),
+            _ => Ok(paper_size(self.format.as_deref())),
+        }
+    }
+
+    /// Budget of a wait with its own timeout `timeout_ms`, falling back to `wait_timeout_ms`
+    fn wait_timeout(&self, timeout_ms: Option<u64>) -> Option<Duration> {
+        timeout_ms
+            .or(self.wait_timeout_ms)
+            .map(Duration::from_millis)
+    }
+
+    fn is_untrusted(&self) -> bool {
+        self.render_mode == Some(RenderMode::Untrusted)
+    }
+
+    fn scripts_disabled(&self) -> bool {
+        self.javascript_enabled == Some(false) || self.is_untrusted()
+    }
+
+    /// URL patterns the page must not load
+    fn blocked_urls(&self) -> Vec<String> {
+        let mut patterns = Vec::new();
+        if self.is_untrusted() {
+            patterns.extend(UNTRUSTED_BLOCKED_URLS.iter().map(|u| u.to_string()));
+        }
+        if self.load_images == Some(false) {
+            for extension in IMAGE_EXTENSIONS {
+                patterns.push(format!("*.{extension}"));
+                patterns.push(format!("*.{extension}?*"));
+            }
+        }
+        patterns
+    }
+
+    /// Post-processing steps the payload asks for; `margins` are the driver's defaults
+    fn post_process(&self, margins: Margins) -> Result<PostProcess> {
+        // Generated page numbers get a margin of their own, as in `render_document`
+        let numbers_on_top = self
+            .page_numbers
+            .as_ref()
+            .map(|spec| spec.position.is_top());
+        let template_margin = |margin: Option<f64>, on_this_side: bool, default: f64| {
+            margin
+                .or(on_this_side.then_some(DEFAULT_TEMPLATE_MARGIN))
+                .unwrap_or(default)
+        };
+
+        Ok(PostProcess {
+            pdf_version: self.pdf_version.clone(),
+            attachments: if self.embed_source == Some(true) {
+                self.source_attachments()?
+            } else {
+                Vec::new()
+            },
+            info: DocumentInfo {
+                title: self.title.clone(),
+                author: self.author.clone(),
+                subject: self.subject.clone(),
+                keywords: self.keywords.as_ref().map(|keywords| keywords.join(", ")),
+                creator: self.creator.clone(),
+                creation_date: self.creation_date.clone(),
+            },
+            flatten: self.flatten == Some(true),
+            compression: self.compression.unwrap_or_default(),
+            header_footer_pages: self.header_footer_page_ranges.as_ref().map(|ranges| {
+                HeaderFooterPages {
+                    ranges: ranges.clone(),
+                    top: template_margin(
+                        self.margin_top,
+                        numbers_on_top == Some(true),
+                        margins.top,
+                    ),
+                    bottom: template_margin(
+                        self.margin_bottom,
+                        numbers_on_top == Some(false),
+                        margins.bottom,
+                    ),
+                }
+            }),
+        })
+    }
 
-fn format_to_inches(format: &str) -> (f64, f64) {
-    match format.to_uppercase().as_str() {
-        "LETTER" => (8.5, 11.0),
-        "LEGAL" => (8.5, 14.0),
-        "TABLOID" => (11.0, 17.0),
-        "LEDGER" => (17.0, 11.0),
-        "A0" => (33.1, 46.8),
-        "A1" => (23.4, 33.1),
-        "A2" => (16.5, 23.4),
-        "A3" => (11.7, 16.5),
-        "A4" => (8.27, 11.7),
-        "A5" => (5.83, 8.27),
-        "A6" => (4.13, 5.83),
-        _ => (8.27, 11.7), // Default to A4
+    /// The source HTML, if any, and the payload as JSON
+    fn source_attachments(&self) -> Result<Vec<Attachment>> {
+        let mut attachments = Vec::new();
+
+        if let Some(html) = &self.html {
+            attachments.push(Attachment {
+                name: "source.html".to_string(),
+                mime_type: "text/html".to_string(),
+                description: Some("HTML the document was rendered from".to_string()),
+                data: html.clone().into_bytes(),
+            });
+        }
+
+        let payload = ChromeDriverPdfPayload {
+            login: None,
+            ..self.clone()
+        };
+        attachments.push(Attachment {
+            name: "payload.json".to_string(),
+            mime_type: "application/json".to_string(),
+            description: Some("Render request, without login credentials".to_string()),
+            data: serde_json::to_vec_pretty(&payload).wrap_err("Failed to serialize payload")?,
+        });
+
+        Ok(attachments)
+    }
+}
+
+/// Hide everything except the element matched by `selector` so only it gets printed.
+///
+/// The element is pinned to the top-left corner of the first page.
+async fn isolate_selector(page: &Page, selector: &str) -> Result<()> {
+    let script = format!(
+        r#"(() => {{
+            const target = document.querySelector({selector});
+            if (!target) return false;
+            target.setAttribute('data-pdfan-print-target', '');
+            const style = document.createElement('style');
+            style.textContent = `
+                body * {{ visibility: hidden !important; }}
+                [data-pdfan-print-target], [data-pdfan-print-target] * {{ visibility: visible !important; }}
+                [data-pdfan-print-target] {{ position: absolute !important; left: 0 !important; top: 0 !important; }}
+            `;
+            (document.head || document.documentElement).appendChild(style);
+            return true;
+        }})()"#,
+        selector = serde_json::to_string(selector)?
+    );
+
+    let found: bool = page
+        .evaluate(script)
+        .await
+        .wrap_err("Failed to apply print_selector")?
+        .into_value()?;
+
+    if !found {
+        return Err(eyre!(
+            "print_selector `{}` did not match any element",
+            selector
+        ));
+    }
+
+    Ok(())
+}
+
+/// Break the page after every element matching `selector` but the last, which would
+/// otherwise leave a blank page at the end
+async fn page_per_element(page: &Page, selector: &str) -> Result<()> {
+    let script = format!(
+        r#"(() => {{
+            const targets = document.querySelectorAll({selector});
+            if (targets.length === 0) return false;
+            targets.forEach((target, i) => {{
+                if (i < targets.length - 1) target.setAttribute('data-pdfan-page-break', '');
+            }});
+            const style = document.createElement('style');
+            style.textContent = `
+                [data-pdfan-page-break] {{ break-after: page !important; page-break-after: always !important; }}
+            `;
+            (document.head || document.documentElement).appendChild(style);
+            return true;
+        }})()"#,
+        selector = serde_json::to_string(selector)?
+    );
+
+    let found: bool = page
+        .evaluate(script)
+        .await
+        .wrap_err("Failed to apply page_per_selector")?
+        .into_value()?;
+
+    if !found {
+        return Err(eyre!(
+            "page_per_selector `{}` did not match any element",
+            selector
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rough height in inches of a header or footer `template` laid out `width` inches wide.
+///
+/// Measured in a detached shadow root, so the template's styles don't leak into the page.
+async fn template_height(page: &Page, template: &str, width: f64) -> Result<f64> {
+    let script = format!(
+        r#"(() => {{
+            const host = document.createElement('div');
+            host.style.cssText = 'position: absolute; left: -10000px; top: 0; width: {width}in;';
+            const root = host.attachShadow({{ mode: 'open' }});
+            root.innerHTML = {template};
+            document.documentElement.appendChild(host);
+            const height = host.getBoundingClientRect().height;
+            host.remove();
+            return height;
+        }})()"#,
+        width = width.max(0.0),
+        template = serde_json::to_string(template)?
+    );
+
+    let height: f64 = page
+        .evaluate(script)
+        .await
+        .wrap_err("Failed to measure the header/footer template")?
+        .into_value()?;
+    // CSS pixels are 1/96 in
+    Ok(height / 96.0)
+}
+
+/// Width the content needs when laid out `width` inches wide, as a share of that width.
+///
+/// Above 1 when something, like a wide table, doesn't fit and overflows.
+async fn content_overflow(page: &Page, width: f64) -> Result<f64> {
+    let script = format!(
+        r#"(() => {{
+            const root = document.documentElement;
+            const previous = root.style.width;
+            root.style.width = '{width}in';
+            const needed = Math.max(root.scrollWidth, document.body ? document.body.scrollWidth : 0);
+            const available = root.clientWidth;
+            root.style.width = previous;
+            return available > 0 ? needed / available : 1;
+        }})()"#,
+        width = width.max(0.0)
+    );
+
+    page.evaluate(script)
+        .await
+        .wrap_err("Failed to measure the content for autoOrient")?
+        .into_value()
+        .map_err(Into::into)
+}
+
+/// Stop Chrome from dropping background colors and images to save ink when printing
+async fn keep_backgrounds(page: &Page) -> Result<()> {
+    page.evaluate(
+        r#"(() => {
+            const style = document.createElement('style');
+            style.textContent = `* { -webkit-print-color-adjust: exact !important; print-color-adjust: exact !important; }`;
+            (document.head || document.documentElement).appendChild(style);
+        })()"#,
+    )
+    .await
+    .wrap_err("Failed to keep backgrounds")?;
+    Ok(())
+}
+
+/// `Page.printToPDF` with untyped parameters, for ones `PrintToPdfParams` doesn't know yet
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+struct RawPrintToPdf(serde_json::Map<String, serde_json::Value>);
+
+impl Method for RawPrintToPdf {
+    fn identifier(&self) -> MethodId {
+        PrintToPdfParams::IDENTIFIER.into()
+    }
+}
+
+impl Command for RawPrintToPdf {
+    type Response = PrintToPdfReturns;
+}
+
+/// Print with `params` plus the fields of `extra` that `params` doesn't set, keeping
+/// what Chrome answered besides the PDF
+async fn print_raw(
+    page: &Page,
+    params: PrintToPdfParams,
+    extra: Option<&serde_json::Value>,
+) -> Result<(Vec<u8>, PrintInfo)> {
+    let mut merged = extra
+        .and_then(|extra| extra.as_object().cloned())
+        .unwrap_or_default();
+    if let serde_json::Value::Object(typed) = serde_json::to_value(params)? {
+        merged.extend(typed);
+    }
+
+    let response = page
+        .execute(RawPrintToPdf(merged))
+        .await
+        .wrap_err("Failed to generate PDF")?;
+    let pdf = base64::engine::general_purpose::STANDARD
+        .decode::<&str>(response.result.data.as_ref())
+        .wrap_err("Chrome returned invalid PDF data")?;
+    let info = PrintInfo {
+        stream: response.result.stream.is_some(),
+        bytes: pdf.len(),
+    };
+    Ok((pdf, info))
+}
+
+/// Append `css` in a `<style>` after all of the page's own styles
+async fn inject_css(page: &Page, css: &str) -> Result<()> {
+    let script = format!(
+        r#"(() => {{
+            const style = document.createElement('style');
+            style.textContent = {css};
+            (document.head || document.documentElement).appendChild(style);
+        }})()"#,
+        css = serde_json::to_string(css)?
+    );
+    page.evaluate(script)
+        .await
+        .wrap_err("Failed to inject CSS")?;
+    Ok(())
+}
+
+/// The main document turned out to be a PDF, which is fetched as is instead of printed
+#[derive(Debug)]
+struct TargetIsPdf {
+    url: String,
+}
+
+impl std::fmt::Display for TargetIsPdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is a PDF", self.url)
+    }
+}
+
+impl std::error::Error for TargetIsPdf {}
+
+/// Reason given when the main document is served as an attachment
+const DOWNLOAD_REASON: &str = "target URL is a download, not a renderable page";
+
+/// Whether response `headers` ask the browser to download the body instead of showing it
+fn is_attachment(headers: &Headers) -> bool {
+    headers.inner().as_object().is_some_and(|headers| {
+        headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("content-disposition")
+                && value.as_str().is_some_and(|value| {
+                    value
+                        .trim_start()
+                        .get(..10)
+                        .is_some_and(|kind| kind.eq_ignore_ascii_case("attachment"))
+                })
+        })
+    })
+}
+
+/// Navigate to `url`, failing as soon as the main document can't be fetched.
+///
+/// Chrome may otherwise show its error page and keep the navigation going until the
+/// timeout when the host refuses the connection or doesn't resolve. A main document
+/// served as an attachment fails too, since Chrome aborts the navigation to download it,
+/// unless it is a PDF, which ends the navigation with `TargetIsPdf` instead.
+async fn navigate(page: &Page, url: &str) -> Result<()> {
+    let main_frame = page.mainframe().await?;
+    let mut requests = page.event_listener::<EventRequestWillBeSent>().await?;
+    let mut responses = page.event_listener::<EventResponseReceived>().await?;
+    let mut failures = page.event_listener::<EventLoadingFailed>().await?;
+
+    let navigation_error = |reason: String| PdfError::Navigation {
+        url: url.to_string(),
+        reason,
+    };
+    let document_failed = async {
+        let mut documents = HashSet::new();
+        loop {
+            tokio::select! {
+                // Requests first, so a failure is never seen before its request
+                biased;
+                Some(request) = requests.next() => {
+                    if request.r#type == Some(ResourceType::Document)
+                        && request.frame_id.is_some()
+                        && request.frame_id == main_frame
+                    {
+                        documents.insert(request.request_id.clone());
+                    }
+                }
+                Some(response) = responses.next() => {
+                    if !documents.contains(&response.request_id) {
+                        continue;
+                    }
+                    if response.response.mime_type == "application/pdf" {
+                        return color_eyre::Report::new(TargetIsPdf { url: response.response.url.clone() });
+                    }
+                    if is_attachment(&response.response.headers) {
+                        return navigation_error(DOWNLOAD_REASON.to_string()).into();
+                    }
+                }
+                Some(failure) = failures.next() => {
+                    if documents.contains(&failure.request_id) && failure.canceled != Some(true) {
+                        return navigation_error(failure.error_text.clone()).into();
+                    }
+                }
+                else => std::future::pending::<()>().await,
+            }
+        }
+    };
+
+    tokio::select! {
+        // The download is aborted right after its response, so report that first
+        biased;
+        error = document_failed => Err(error),
+        result = page.goto(url) => match result {
+            Ok(_) => Ok(()),
+            Err(CdpError::ChromeMessage(reason)) => Err(navigation_error(reason).into()),
+            Err(e) => Err(e).wrap_err("Failed to navigate to URL"),
+        },
+    }
+}
+
+/// Fill and submit a login form, waiting until the page shows it succeeded
+async fn login(page: &Page, spec: &LoginSpec) -> Result<()> {
+    page.goto(spec.url.as_str())
+        .await
+        .wrap_err("Failed to navigate to login page")?;
+
+    page.find_element(spec.username_selector.as_str())
+        .await
+        .wrap_err("Login username field not found")?
+        .click()
+        .await?
+        .type_str(&spec.username)
+        .await?;
+
+    let password = page
+        .find_element(spec.password_selector.as_str())
+        .await
+        .wrap_err("Login password field not found")?;
+    password.click().await?.type_str(&spec.password).await?;
+
+    match &spec.submit_selector {
+        Some(selector) => {
+            page.find_element(selector.as_str())
+                .await
+                .wrap_err("Login submit button not found")?
+                .click()
+                .await?;
+        }
+        None => {
+            password.press_key("Enter").await?;
+        }
+    }
+
+    wait_for_selector(page, &spec.success_selector)
+        .await
+        .wrap_err("Login did not succeed")?;
+
+    Ok(())
+}
+
+pub trait PdfDriver {
+    type Payload;
+    fn pdf(
+        &self,
+        payload: Self::Payload,
+    ) -> impl std::future::Future<Output = Result<PdfOutput>> + Send;
+
+    /// Load of the driver's worker pool, for drivers that have one
+    fn stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    /// Whether rendering `payload` again gives the same document, so the result may be
+    /// identified by the payload alone
+    fn is_cacheable(&self, payload: &Self::Payload) -> bool {
+        let _ = payload;
+        false
+    }
+
+    /// What the driver supports as configured, for clients to check payloads against
+    fn capabilities(&self) -> serde_json::Value {
+        serde_json::json!({})
+    }
+
+    /// Load `url` ahead of related renders so they find its resources cached.
+    /// Drivers without such a cache do nothing.
+    fn prime_cache(&self, url: &str) -> impl std::future::Future<Output = Result<()>> + Send {
+        let _ = url;
+        async { Ok(()) }
+    }
+
+    /// Like `pdf`, reporting lifecycle events to `progress` along the way
+    fn pdf_with_progress(
+        &self,
+        payload: Self::Payload,
+        progress: Progress,
+    ) -> impl std::future::Future<Output = Result<PdfOutput>> + Send {
+        let _ = progress;
+        self.pdf(payload)
+    }
+}
+
+/// How the browser is started, the same for every launch
+#[derive(Debug, Clone, Default)]
+struct LaunchOptions {
+    /// Nice value for a launched Chrome and all its processes
+    nice: Option<i32>,
+    /// How long a CDP command may take before it fails, instead of chromiumoxide's default
+    cdp_timeout: Option<Duration>,
+    /// Connect to the browser at this DevTools URL instead of launching one
+    browser_ws: Option<String>,
+    /// Reject browsers of any other version
+    chrome_version: Option<String>,
+    /// Executable to launch instead of the one chromiumoxide detects
+    chrome_path: Option<PathBuf>,
+}
+
+/// A running browser with its handler task
+struct BrowserInstance {
+    browser: Arc<Browser>,
+    generation: u64,
+    handler_handle: JoinHandle<()>,
+    /// Connected to through `browser_ws` rather than launched, so not ours to close
+    remote: bool,
+}
+
+impl BrowserInstance {
+    /// Launch Chrome, or connect to the one at `browser_ws` when that is set.
+    ///
+    /// A remote browser keeps its own nice value.
+    async fn launch(generation: u64, options: &LaunchOptions) -> Result<Self> {
+        let remote = options.browser_ws.as_ref();
+        let (mut browser, mut handler) = match remote {
+            Some(ws_url) => {
+                let mut config = HandlerConfig::default();
+                if let Some(timeout) = options.cdp_timeout {
+                    config.request_timeout = timeout;
+                }
+                Browser::connect_with_config(ws_url.as_str(), config)
+                    .await
+                    .wrap_err_with(|| format!("Failed to connect to browser at {}", ws_url))?
+            }
+            None => Self::launch_local(options).await?,
+        };
+
+        // Spawn handler task - must run continuously for CDP communication
+        let handler_handle = tokio::spawn(async move {
+            while let Some(event) = handler.next().await {
+                if let Err(e) = event {
+                    eprintln!("Browser handler error: {:?}", e);
+                }
+            }
+        });
+
+        let pid = browser
+            .get_mut_child()
+            .and_then(|child| child.as_mut_inner().id());
+        let reniced = match (options.nice, pid) {
+            (None, _) => Ok(()),
+            _ if remote.is_some() => Ok(()),
+            #[cfg(unix)]
+            (Some(nice), Some(pid)) => crate::priority::renice_tree(pid, nice),
+            (Some(_), _) => Err(eyre!("Cannot set the nice value of the browser process")),
+        };
+
+        let instance = Self {
+            browser: Arc::new(browser),
+            generation,
+            handler_handle,
+            remote: remote.is_some(),
+        };
+
+        if let Err(e) = reniced {
+            instance.close().await;
+            return Err(e);
+        }
+
+        if let Some(expected) = &options.chrome_version
+            && let Err(e) = instance.check_version(expected).await
+        {
+            instance.close().await;
+            return Err(e);
+        }
+
+        // Pages are only ever printed, so nothing they trigger should land on disk
+        if let Err(e) = instance
+            .browser
+            .execute(SetDownloadBehaviorParams::new(
+                SetDownloadBehaviorBehavior::Deny,
+            ))
+            .await
+        {
+            instance.close().await;
+            return Err(e).wrap_err("Failed to disable downloads");
+        }
+
+        Ok(instance)
+    }
+
+    async fn launch_local(options: &LaunchOptions) -> Result<(Browser, Handler)> {
+        let mut builder = BrowserConfig::builder()
+            .arg("--headless")
+            .arg("--no-sandbox")
+            .arg("--disable-gpu")
+            .arg("--disable-dev-shm-usage");
+        if let Some(timeout) = options.cdp_timeout {
+            builder = builder.request_timeout(timeout);
+        }
+        if options.nice.is_some() {
+            // Renderers are otherwise forked by the zygote, which may start before the renice
+            builder = builder.arg("--no-zygote");
+        }
+
+        let path = options.chrome_path.as_ref();
+        if let Some(path) = path {
+            if !path.is_file() {
+                return Err(eyre!(
+                    "The Chrome path is set to {}, which is not a file",
+                    path.display()
+                ));
+            }
+            builder = builder.chrome_executable(path);
+        }
+
+        let config = builder.build().map_err(|e| {
+            eyre!(
+                "Failed to build browser config: {} (set PDFAN_CHROME_PATH to the Chrome binary)",
+                e
+            )
+        })?;
+
+        Browser::launch(config).await.wrap_err_with(|| match path {
+            Some(path) => format!("Failed to launch browser at {}", path.display()),
+            None => "Failed to launch browser".to_string(),
+        })
+    }
+
+    async fn check_version(&self, expected: &str) -> Result<()> {
+        let product = self
+            .browser
+            .version()
+            .await
+            .wrap_err("Failed to query browser version")?
+            .product;
+        // e.g. `HeadlessChrome/126.0.6478.126`
+        let version = product.split_once('/').map_or(product.as_str(), |(_, v)| v);
+
+        if version == expected || version.starts_with(&format!("{}.", expected)) {
+            Ok(())
+        } else {
+            Err(eyre!(
+                "Chrome version {} does not match the expected {}",
+                version,
+                expected
+            ))
+        }
+    }
+
+    async fn close(self) {
+        // Pages don't hold the browser, so this is normally the last reference.
+        // A remote browser outlives us, dropping it only closes the connection.
+        if !self.remote
+            && let Ok(mut browser) = Arc::try_unwrap(self.browser)
+        {
+            if let Err(e) = browser.close().await {
+                eprintln!("Failed to close browser: {:?}", e);
+            }
+            let _ = browser.wait().await;
+        }
+        self.handler_handle.abort();
+    }
+}
+
+/// Browser shared by all workers.
+///
+/// The browser is launched on demand, so it can be shut down while idle and
+/// transparently relaunched by the next task. Every launch bumps the generation,
+/// which tells workers that their page belongs to a browser that is gone.
+struct SharedBrowser {
+    instance: tokio::sync::Mutex<Option<BrowserInstance>>,
+    next_generation: AtomicU64,
+    active: AtomicUsize,
+    last_used: std::sync::Mutex<Instant>,
+    /// Limits concurrent page creation, so a pool recovering all at once doesn't flood CDP
+    page_creation: tokio::sync::Semaphore,
+    /// Used for every launch of the browser
+    options: LaunchOptions,
+}
+
+impl SharedBrowser {
+    async fn launch(page_creation_limit: usize, options: LaunchOptions) -> Result<Self> {
+        let instance = BrowserInstance::launch(0, &options).await?;
+
+        Ok(Self {
+            instance: tokio::sync::Mutex::new(Some(instance)),
+            next_generation: AtomicU64::new(1),
+            active: AtomicUsize::new(0),
+            last_used: std::sync::Mutex::new(Instant::now()),
+            page_creation: tokio::sync::Semaphore::new(page_creation_limit.max(1)),
+            options,
+        })
+    }
+
+    /// Open a blank page in the running browser, returning it with the browser's generation
+    async fn new_page(&self) -> Result<(Page, u64)> {
+        let _permit = self
+            .page_creation
+            .acquire()
+            .await
+            .wrap_err("Page creation closed")?;
+        let (browser, generation) = self.browser().await?;
+        let page = browser.new_page("about:blank").await?;
+        Ok((page, generation))
+    }
+
+    /// Get the running browser and its generation, launching it if it was shut down
+    async fn browser(&self) -> Result<(Arc<Browser>, u64)> {
+        let mut instance = self.instance.lock().await;
+
+        if instance.is_none() {
+            let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+            *instance = Some(BrowserInstance::launch(generation, &self.options).await?);
+        }
+
+        let instance = instance.as_ref().expect("browser was just launched");
+        Ok((Arc::clone(&instance.browser), instance.generation))
+    }
+
+    /// Mark the browser as in use until the returned guard is dropped
+    fn lease(&self) -> BrowserLease<'_> {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        BrowserLease { shared: self }
+    }
+
+    /// Close the browser if no task has used it for `idle_timeout`
+    async fn shutdown_if_idle(&self, idle_timeout: Duration) {
+        let mut instance = self.instance.lock().await;
+
+        let idle_for = self.last_used.lock().unwrap().elapsed();
+        if instance.is_none() || self.active.load(Ordering::SeqCst) > 0 || idle_for < idle_timeout {
+            return;
+        }
+
+        if let Some(instance) = instance.take() {
+            println!("Shutting down idle browser after {:?}", idle_for);
+            instance.close().await;
+        }
+    }
+}
+
+struct BrowserLease<'a> {
+    shared: &'a SharedBrowser,
+}
+
+impl Drop for BrowserLease<'_> {
+    fn drop(&mut self) {
+        *self.shared.last_used.lock().unwrap() = Instant::now();
+        self.shared.active.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct ChromeDriverPdfPayload {
-    url: Option<String>,
-    html: Option<String>,
-    title: Option<String>,
-    author: Option<String>,
-    media: Option<String>,
-    format: Option<String>,
-    width: Option<String>,
-    height: Option<String>,
-    print_range: Option<String>,
-    #[serde(default)]
-    print_background: bool,
-    #[serde(default)]
-    landscape: bool,
-    margin_top: Option<f64>,
-    margin_right: Option<f64>,
-    margin_bottom: Option<f64>,
-    margin_left: Option<f64>,
-    #[serde(default)]
-    display_header_footer: bool,
-    header_template: Option<String>,
-    footer_template: Option<String>,
-    wait_for_resources: Option<bool>,
-    #[serde(default)]
-    wait_for_event: bool,
-}
+/// Worker context holding a reusable page
+pub struct ChromeTaskCtx {
+    shared: Arc<SharedBrowser>,
+    page: Page,
+    generation: u64,
+    overrides: PageOverrides,
+}
+
+/// Emulation state applied to the reused page; the default matches a fresh page.
+///
+/// Tasks only send CDP commands when their wanted state differs from this, and
+/// whatever one task changes is reset by the next task that doesn't ask for it.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PageOverrides {
+    scripts_disabled: bool,
+    accept_language: Option<String>,
+    blocked_urls: Vec<String>,
+    device: Option<&'static str>,
+    /// Emulated media type, empty for none
+    media: String,
+    media_features: Vec<(String, String)>,
+    background_color: Option<Rgba>,
+    /// Stays installed once added, there is nothing to reset
+    render_binding: bool,
+    /// Stays installed once added, like `render_binding`
+    dom_binding: bool,
+}
+
+impl PageOverrides {
+    async fn set_scripts_disabled(&mut self, page: &Page, disabled: bool) -> Result<()> {
+        if self.scripts_disabled != disabled {
+            page.execute(SetScriptExecutionDisabledParams::new(disabled))
+                .await
+                .wrap_err("Failed to toggle JavaScript")?;
+            self.scripts_disabled = disabled;
+        }
+
+        Ok(())
+    }
+
+    async fn set_blocked_urls(&mut self, page: &Page, patterns: Vec<String>) -> Result<()> {
+        if self.blocked_urls != patterns {
+            page.execute(SetBlockedUrLsParams::new(patterns.clone()))
+                .await
+                .wrap_err("Failed to set blocked URLs")?;
+            self.blocked_urls = patterns;
+        }
+
+        Ok(())
+    }
+
+    async fn set_device(&mut self, page: &Page, device: Option<&'static Device>) -> Result<()> {
+        let name = device.map(|d| d.name);
+        if self.device == name {
+            return Ok(());
+        }
+
+        match device {
+            Some(device) => {
+                page.execute(SetDeviceMetricsOverrideParams::new(
+                    device.width,
+                    device.height,
+                    device.device_scale_factor,
+                    device.mobile,
+                ))
+                .await
+                .wrap_err("Failed to emulate device metrics")?;
+                page.execute(SetUserAgentOverrideParams::new(device.user_agent))
+                    .await
+                    .wrap_err("Failed to emulate device user agent")?;
+            }
+            None => {
+                page.execute(ClearDeviceMetricsOverrideParams::default())
+                    .await
+                    .wrap_err("Failed to reset device metrics")?;
+                // An empty user agent removes the override
+                page.execute(SetUserAgentOverrideParams::new(""))
+                    .await
+                    .wrap_err("Failed to reset user agent")?;
+            }
+        }
+        self.device = name;
+
+        Ok(())
+    }
+
+    /// Emulate the `media` type (empty for none) and `features` in one command, since
+    /// Chrome resets whichever of the two a `setEmulatedMedia` leaves out
+    async fn set_emulated_media(
+        &mut self,
+        page: &Page,
+        media: &str,
+        features: &[(String, String)],
+    ) -> Result<()> {
+        if self.media == media && self.media_features == features {
+            return Ok(());
+        }
+
+        page.execute(
+            SetEmulatedMediaParams::builder()
+                .media(media)
+                .features(
+                    features
+                        .iter()
+                        .map(|(name, value)| MediaFeature::new(name, value)),
+                )
+                .build(),
+        )
+        .await
+        .wrap_err("Failed to emulate media")?;
+        self.media = media.to_string();
+        self.media_features = features.to_vec();
+
+        Ok(())
+    }
+
+    async fn set_background_color(&mut self, page: &Page, color: Option<Rgba>) -> Result<()> {
+        if self.background_color != color {
+            // Without a color, the override is cleared
+            page.execute(SetDefaultBackgroundColorOverrideParams {
+                color: color.clone(),
+            })
+            .await
+            .wrap_err("Failed to set the background color")?;
+            self.background_color = color;
+        }
+
+        Ok(())
+    }
+
+    async fn ensure_dom_binding(&mut self, page: &Page) -> Result<()> {
+        if !self.dom_binding {
+            install_dom_mutation_binding(page)
+                .await
+                .wrap_err("Failed to install the DOM mutation binding")?;
+            self.dom_binding = true;
+        }
+
+        Ok(())
+    }
+
+    async fn ensure_render_binding(&mut self, page: &Page) -> Result<()> {
+        if !self.render_binding {
+            install_render_binding(page)
+                .await
+                .wrap_err("Failed to install the render event binding")?;
+            self.render_binding = true;
+        }
+
+        Ok(())
+    }
+
+    async fn set_accept_language(&mut self, page: &Page, languages: Option<&str>) -> Result<()> {
+        if self.accept_language.as_deref() == languages {
+            return Ok(());
+        }
+
+        let headers = match languages {
+            Some(languages) => serde_json::json!({ "Accept-Language": languages }),
+            None => serde_json::json!({}),
+        };
+        page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers)))
+            .await
+            .wrap_err("Failed to set Accept-Language")?;
 
-pub trait PdfDriver {
-    type Payload;
-    async fn pdf(&self, payload: Self::Payload) -> Result<Vec<u8>>;
+        // Chrome refuses to replace an active locale override, so clear it first
+        if self.accept_language.is_some() {
+            page.execute(SetLocaleOverrideParams::default())
+                .await
+                .wrap_err("Failed to reset locale")?;
+        }
+        self.accept_language = None;
+        if let Some(languages) = languages {
+            page.execute(SetLocaleOverrideParams {
+                locale: primary_locale(languages),
+            })
+            .await
+            .wrap_err("Failed to set locale")?;
+            self.accept_language = Some(languages.to_string());
+        }
+
+        Ok(())
+    }
 }
 
-/// Shared browser instance with its handler task
-struct SharedBrowser {
+/// Page in a browser context of its own, for renders that must not share any state
+struct IsolatedPage {
     browser: Arc<Browser>,
-    _handler_handle: JoinHandle<()>,
+    context_id: BrowserContextId,
+    page: Page,
 }
 
-impl SharedBrowser {
-    async fn launch() -> Result<Self> {
-        let config = BrowserConfig::builder()
-            .arg("--headless")
-            .arg("--no-sandbox")
-            .arg("--disable-gpu")
-            .arg("--disable-dev-shm-usage")
-            .build()
-            .map_err(|e| eyre!("Failed to build browser config: {}", e))?;
-
-        let (browser, mut handler) = Browser::launch(config)
+impl IsolatedPage {
+    async fn open(browser: Arc<Browser>) -> Result<Self> {
+        let context_id = browser
+            .create_browser_context(CreateBrowserContextParams::default())
             .await
-            .wrap_err("Failed to launch browser")?;
+            .wrap_err("Failed to create browser context")?;
 
-        // Spawn handler task - must run continuously for CDP communication
-        let handler_handle = tokio::spawn(async move {
-            while let Some(event) = handler.next().await {
-                if let Err(e) = event {
-                    eprintln!("Browser handler error: {:?}", e);
-                }
+        let deny_downloads = SetDownloadBehaviorParams::builder()
+            .behavior(SetDownloadBehaviorBehavior::Deny)
+            .browser_context_id(context_id.clone())
+            .build()
+            .map_err(|e| eyre!("Failed to build download params: {}", e))?;
+        if let Err(e) = browser.execute(deny_downloads).await {
+            let _ = browser.dispose_browser_context(context_id).await;
+            return Err(e).wrap_err("Failed to disable downloads");
+        }
+
+        let params = CreateTargetParams::builder()
+            .url("about:blank")
+            .browser_context_id(context_id.clone())
+            .build()
+            .map_err(|e| eyre!("Failed to build page params: {}", e))?;
+        let page = match browser.new_page(params).await {
+            Ok(page) => page,
+            Err(e) => {
+                let _ = browser.dispose_browser_context(context_id).await;
+                return Err(e).wrap_err("Failed to create isolated page");
             }
-        });
+        };
 
         Ok(Self {
-            browser: Arc::new(browser),
-            _handler_handle: handler_handle,
+            browser,
+            context_id,
+            page,
         })
     }
 
-    fn browser(&self) -> Arc<Browser> {
-        Arc::clone(&self.browser)
+    /// Dispose of the context, which also closes the page
+    async fn close(self) {
+        if let Err(e) = self.browser.dispose_browser_context(self.context_id).await {
+            eprintln!("Failed to dispose browser context: {:?}", e);
+        }
     }
 }
 
-/// Worker context holding a reusable page
-pub struct ChromeTaskCtx {
-    browser: Arc<Browser>,
-    page: Page,
-}
-
 impl ChromeTaskCtx {
-    async fn new(browser: Arc<Browser>) -> Result<Self> {
-        let page = browser
-            .new_page("about:blank")
+    async fn new(shared: Arc<SharedBrowser>) -> Result<Self> {
+        let (page, generation) = shared
+            .new_page()
             .await
             .wrap_err("Failed to create new page")?;
 
-        Ok(Self { browser, page })
+        Ok(Self {
+            shared,
+            page,
+            generation,
+            overrides: PageOverrides::default(),
+        })
     }
 
     /// Recreate the page if it becomes unusable
     async fn recreate_page(&mut self) -> Result<()> {
         // Create fresh page (old page will be dropped, which closes it)
-        self.page = self
-            .browser
-            .new_page("about:blank")
+        let (page, generation) = self
+            .shared
+            .new_page()
             .await
             .wrap_err("Failed to recreate page")?;
+        self.page = page;
+        self.generation = generation;
+        self.overrides = PageOverrides::default();
+
+        Ok(())
+    }
+
+    /// Make sure the page belongs to the running browser, relaunching it if needed
+    async fn ensure_page(&mut self) -> Result<()> {
+        let (_, generation) = self.shared.browser().await?;
+        if generation != self.generation {
+            self.recreate_page().await?;
+        }
 
         Ok(())
     }
 }
 
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
 struct ChromeTask {
+    id: u64,
     payload: ChromeDriverPdfPayload,
+    /// End-to-end deadline fixed when the request came in, shared by every step
+    deadline: tokio::time::Instant,
+    progress: Progress,
+    /// Stamp the trial notice into the footer, whatever the payload asks for
+    trial: bool,
+    /// Retry a failed render once on a fresh page
+    recover: bool,
+    /// Margins for the sides the payload leaves out
+    default_margins: Margins,
+    /// Longest Chrome may take to print, on top of the deadline
+    print_timeout: Option<Duration>,
+    /// Share of their timeouts the waits get
+    wait_budget: WaitBudget,
+}
+
+/// How the driver wants a task rendered, whatever its payload says
+struct TaskOptions {
+    trial: bool,
+    recover: bool,
+    default_margins: Margins,
+    print_timeout: Option<Duration>,
+    wait_budget: WaitBudget,
 }
 
 impl ChromeTask {
-    pub fn new(payload: ChromeDriverPdfPayload) -> Self {
-        Self { payload }
+    fn new(
+        payload: ChromeDriverPdfPayload,
+        deadline: tokio::time::Instant,
+        progress: Progress,
+        options: TaskOptions,
+    ) -> Self {
+        let TaskOptions {
+            trial,
+            recover,
+            default_margins,
+            print_timeout,
+            wait_budget,
+        } = options;
+        Self {
+            id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+            payload,
+            deadline,
+            progress,
+            trial,
+            recover,
+            default_margins,
+            print_timeout,
+            wait_budget,
+        }
+    }
+
+    /// Run one step of the render with whatever is left of the request's budget
+    async fn before_deadline<T>(
+        &self,
+        step: &str,
+        future: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        tokio::time::timeout_at(self.deadline, future)
+            .instrument(tracing::info_span!("render_step", step))
+            .await
+            .wrap_err_with(|| format!("Deadline exceeded while {}", step))?
+    }
+
+    /// Wait for the page to get ready, returning whether it did.
+    ///
+    /// Best-effort renders give up early enough to still print the page as it is,
+    /// everything else fails once the deadline or the wait's own `timeout` passes.
+    async fn wait_before_deadline(
+        &self,
+        step: &str,
+        timeout: Option<Duration>,
+        future: impl std::future::Future<Output = Result<()>>,
+    ) -> Result<bool> {
+        // Waits without a timeout of their own have the rest of the deadline to cut from
+        let timeout = match timeout {
+            Some(timeout) => Some(self.wait_budget.apply(timeout)),
+            None if self.wait_budget != WaitBudget::FULL => Some(
+                self.wait_budget.apply(
+                    self.deadline
+                        .saturating_duration_since(tokio::time::Instant::now()),
+                ),
+            ),
+            None => None,
+        };
+        let timeout_at = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+        if self.payload.best_effort != Some(true) {
+            match timeout
+                .zip(timeout_at)
+                .filter(|(_, at)| *at < self.deadline)
+            {
+                Some((timeout, timeout_at)) => {
+                    tokio::time::timeout_at(timeout_at, self.before_deadline(step, future))
+                        .await
+                        .map_err(|_| eyre!("Timed out after {:?} while {}", timeout, step))??
+                }
+                None => self.before_deadline(step, future).await?,
+            }
+            return Ok(true);
+        }
+
+        let mut cutoff = self
+            .deadline
+            .checked_sub(BEST_EFFORT_PRINT_RESERVE)
+            .unwrap_or(self.deadline);
+        if let Some(timeout_at) = timeout_at {
+            cutoff = cutoff.min(timeout_at);
+        }
+        match tokio::time::timeout_at(cutoff, future)
+            .instrument(tracing::info_span!("render_step", step))
+            .await
+        {
+            Ok(result) => result.map(|()| true),
+            Err(_) => {
+                println!(
+                    "Task {}: stopped {} to print what has loaded",
+                    self.id, step
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    /// Render on the worker's page, or on a throwaway isolated one for untrusted content
+    async fn render(&self, ctx: &mut ChromeTaskCtx) -> Result<PdfOutput> {
+        if !self.payload.is_untrusted() && self.payload.isolated != Some(true) {
+            return self.process_inner(&ctx.page, &mut ctx.overrides).await;
+        }
+
+        let (browser, _) = ctx.shared.browser().await?;
+        let isolated = IsolatedPage::open(browser).await?;
+        // For untrusted renders, network access is blocked by `process_inner` before anything loads
+        let result = self
+            .process_inner(&isolated.page, &mut PageOverrides::default())
+            .await;
+        isolated.close().await;
+
+        result
     }
 
-    async fn process_inner(&self, ctx: &mut ChromeTaskCtx) -> Result<Vec<u8>> {
+    async fn process_inner(&self, page: &Page, overrides: &mut PageOverrides) -> Result<PdfOutput> {
         let p = &self.payload;
 
-        if let Some(media) = &p.media {
-            ctx.page
-                .emulate_media_type(match media.deref() {
-                    "null" => MediaTypeParams::Null,
-                    "screen" => MediaTypeParams::Screen,
-                    "print" => MediaTypeParams::Print,
-                    _ => MediaTypeParams::Null,
-                })
+        if p.log_resource_usage {
+            ResourceUsage::enable(page).await?;
+        }
+
+        overrides
+            .set_scripts_disabled(page, p.scripts_disabled())
+            .await?;
+        overrides
+            .set_accept_language(page, p.accept_language.as_deref())
+            .await?;
+        overrides.set_blocked_urls(page, p.blocked_urls()).await?;
+        overrides
+            .set_device(page, p.device.as_deref().and_then(devices::find))
+            .await?;
+
+        let media = match p.media.as_deref() {
+            Some("screen") => "screen",
+            Some("print") => "print",
+            _ => "",
+        };
+        overrides
+            .set_emulated_media(page, media, p.media_features.as_deref().unwrap_or_default())
+            .await?;
+        overrides
+            .set_background_color(page, p.background_color.as_deref().and_then(parse_color))
+            .await?;
+
+        if let Some(spec) = &p.login {
+            self.before_deadline("logging in", login(page, spec))
                 .await?;
         }
 
+        let output = match p.page_urls() {
+            Some(urls) => {
+                // Render each page of the sequence into its own PDF, then stitch them together
+                let mut pdfs = Vec::with_capacity(urls.len());
+                let mut truncated = false;
+                let mut html = Vec::new();
+                let mut print = Vec::new();
+                for (i, url) in urls.iter().enumerate() {
+                    let output = self
+                        .render_document(page, overrides, Some(url))
+                        .await
+                        .wrap_err_with(|| format!("Failed to render page {} ({})", i + 1, url))?;
+                    truncated |= output.truncated;
+                    html.extend(output.html);
+                    print.extend(output.print);
+                    pdfs.push(output.pdf);
+                }
+
+                let pdf = tokio::task::spawn_blocking(move || merge(&pdfs))
+                    .await
+                    .wrap_err("Merging panicked")??;
+                PdfOutput {
+                    pdf,
+                    truncated,
+                    html,
+                    print,
+                    ..PdfOutput::default()
+                }
+            }
+            None => {
+                self.render_document(page, overrides, p.url.as_deref())
+                    .await?
+            }
+        };
+
+        if p.log_resource_usage {
+            let usage = ResourceUsage::capture(page).await?;
+            println!(
+                "Task {}: {} bytes of PDF, {}",
+                self.id,
+                output.pdf.len(),
+                usage
+            );
+        }
+
+        Ok(output)
+    }
+
+    /// Load `url`, or the payload's HTML when it's `None`, and print it
+    async fn render_document(
+        &self,
+        page: &Page,
+        overrides: &mut PageOverrides,
+        url: Option<&str>,
+    ) -> Result<PdfOutput> {
+        let p = &self.payload;
+        let mut ready = true;
+
+        let console_errors = if p.fail_on_console_error == Some(true) {
+            Some(ConsoleErrors::watch(page).await?)
+        } else {
+            None
+        };
+        let resource_errors = if p.fail_on_resource_error == Some(true) {
+            let patterns = p.critical_resources.clone().unwrap_or_default();
+            Some(ResourceErrors::watch(page, patterns).await?)
+        } else {
+            None
+        };
+
+        self.progress.report(RenderEvent::Navigating);
+
         // Load content - set_content for HTML (fast!), goto for URLs
         if let Some(html) = &p.html {
-            ctx.page
-                .set_content(html)
-                .await
-                .wrap_err("Failed to set HTML content")?;
-        } else if let Some(url) = &p.url {
+            self.before_deadline("setting HTML content", async {
+                match tokio::time::timeout(SET_CONTENT_TIMEOUT, page.set_content(html)).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(CdpError::JavascriptException(exception))) => {
+                        Err(PdfError::BadContent(exception.text).into())
+                    }
+                    Ok(Err(e)) => Err(e).wrap_err("Failed to set HTML content"),
+                    Err(_) => Err(PdfError::BadContent(format!(
+                        "did not finish loading within {:?}",
+                        SET_CONTENT_TIMEOUT
+                    ))
+                    .into()),
+                }
+            })
+            .await?;
+        } else if let Some(url) = url {
             if p.wait_for_event {
-                let wait_future = setup_custom_event_wait(&ctx.page).await?;
-                ctx.page
-                    .goto(url)
-                    .await
-                    .wrap_err("Failed to navigate to URL")?;
-                wait_future.await?;
+                overrides.ensure_render_binding(page).await?;
+                let wait_future = setup_custom_event_wait(page).await?;
+                ready &= self
+                    .wait_before_deadline("navigating", None, navigate(page, url))
+                    .await?;
+                self.progress.report(RenderEvent::WaitingForPage);
+                ready &= self
+                    .wait_before_deadline(
+                        "waiting for the render event",
+                        p.wait_timeout(p.wait_for_event_timeout_ms),
+                        wait_future,
+                    )
+                    .await?;
             } else {
-                ctx.page
-                    .goto(url)
-                    .await
-                    .wrap_err("Failed to navigate to URL")?;
+                ready &= self
+                    .wait_before_deadline("navigating", None, navigate(page, url))
+                    .await?;
 
-                match p.wait_for_resources {
-                    Some(true) => {
-                        wait_for_network_idle(&ctx.page, crate::wait::NetworkIdleKind::Idle0)
-                            .await?
-                    }
-                    Some(false) => {
-                        wait_for_network_idle(&ctx.page, crate::wait::NetworkIdleKind::Idle2)
-                            .await?
-                    }
-                    None => {}
+                let idle_kind = match p.wait_for_resources {
+                    Some(true) => Some(NetworkIdleKind::Idle0),
+                    Some(false) => Some(NetworkIdleKind::Idle2),
+                    None => None,
+                };
+                if let Some(kind) = idle_kind {
+                    self.progress.report(RenderEvent::WaitingForIdle);
+                    ready &= self
+                        .wait_before_deadline(
+                            "waiting for network idle",
+                            p.wait_timeout(p.wait_for_resources_timeout_ms),
+                            wait_for_network_idle(page, kind),
+                        )
+                        .await?;
                 }
             }
         } else {
             return Err(eyre!("Either url or html must be provided"));
         }
 
+        if let Some(expression) = &p.wait_for_expression {
+            self.progress.report(RenderEvent::WaitingForPage);
+            let remaining = self
+                .deadline
+                .saturating_duration_since(tokio::time::Instant::now());
+            ready &= self
+                .wait_before_deadline(
+                    "waiting for the expression",
+                    p.wait_timeout(p.wait_for_expression_timeout_ms),
+                    wait_for_expression(page, expression, remaining),
+                )
+                .await?;
+        }
+
+        if p.wait_for_animations == Some(true) {
+            self.progress.report(RenderEvent::WaitingForPage);
+            let remaining = self
+                .deadline
+                .saturating_duration_since(tokio::time::Instant::now());
+            ready &= self
+                .wait_before_deadline(
+                    "waiting for animations",
+                    p.wait_timeout(p.wait_for_animations_timeout_ms),
+                    wait_for_animations(page, remaining),
+                )
+                .await?;
+        }
+
+        if p.wait_for_dom_stable == Some(true) {
+            self.progress.report(RenderEvent::WaitingForPage);
+            overrides.ensure_dom_binding(page).await?;
+            let quiet_period =
+                Duration::from_millis(p.dom_quiet_period_ms.unwrap_or(DEFAULT_DOM_QUIET_PERIOD_MS));
+            let remaining = self
+                .deadline
+                .saturating_duration_since(tokio::time::Instant::now());
+            ready &= self
+                .wait_before_deadline(
+                    "waiting for the DOM to settle",
+                    p.wait_timeout(p.wait_for_dom_stable_timeout_ms),
+                    wait_for_dom_stable(page, quiet_period, remaining),
+                )
+                .await?;
+        }
+
+        if let Some(selector) = &p.print_selector {
+            isolate_selector(page, selector).await?;
+        }
+
+        if let Some(selector) = &p.page_per_selector {
+            page_per_element(page, selector).await?;
+        }
+
+        if p.print_background {
+            keep_backgrounds(page).await?;
+        }
+
+        // Last, so the caller's CSS wins over the styles added above
+        if let Some(css) = &p.inject_css {
+            inject_css(page, css).await?;
+        }
+
         // Build PDF parameters
-        let display_header_footer = p.header_template.is_some() || p.footer_template.is_some();
+        let mut header_template = p.header_template.clone();
+        let mut footer_template = p.footer_template.clone();
+        let mut margin_top = p.margin_top;
+        let mut margin_bottom = p.margin_bottom;
+
+        if let Some(spec) = &p.page_numbers {
+            // Reserve room for the page numbers unless the caller chose a margin
+            if spec.position.is_top() {
+                header_template = Some(spec.template());
+                margin_top = margin_top.or(Some(DEFAULT_TEMPLATE_MARGIN));
+            } else {
+                footer_template = Some(spec.template());
+                margin_bottom = margin_bottom.or(Some(DEFAULT_TEMPLATE_MARGIN));
+            }
+        }
+
+        if self.trial {
+            footer_template = Some(with_trial_notice(footer_template.as_deref()));
+            margin_bottom = Some(
+                margin_bottom
+                    .unwrap_or(self.default_margins.bottom)
+                    .max(TRIAL_MARGIN),
+            );
+        }
+
+        let display_header_footer = header_template.is_some() || footer_template.is_some();
+        if display_header_footer {
+            header_template.get_or_insert_with(|| EMPTY_TEMPLATE.to_string());
+            footer_template.get_or_insert_with(|| EMPTY_TEMPLATE.to_string());
+        }
+
+        let mut margin_top = margin_top.unwrap_or(self.default_margins.top);
+        let mut margin_bottom = margin_bottom.unwrap_or(self.default_margins.bottom);
+        let margin_right = p.margin_right.unwrap_or(self.default_margins.right);
+        let margin_left = p.margin_left.unwrap_or(self.default_margins.left);
+
+        let (paper_width, paper_height) = p.paper_size()?;
+        let mut landscape = p.landscape;
+        if p.auto_orient == Some(true) {
+            let overflow = content_overflow(page, paper_width - margin_left - margin_right).await?;
+            if overflow > AUTO_ORIENT_OVERFLOW {
+                landscape = true;
+            } else if overflow <= 1.0 {
+                landscape = false;
+            }
+        }
+
+        if display_header_footer {
+            let printed_width = if landscape { paper_height } else { paper_width };
+            let width = printed_width - margin_left - margin_right;
+            let fit = p.template_fit.unwrap_or_default();
+            for (field, template, margin) in [
+                ("marginTop", &header_template, &mut margin_top),
+                ("marginBottom", &footer_template, &mut margin_bottom),
+            ] {
+                let Some(template) = template.as_deref().filter(|t| *t != EMPTY_TEMPLATE) else {
+                    continue;
+                };
+                let height = template_height(page, template, width).await?;
+                if height <= *margin {
+                    continue;
+                }
+                match fit {
+                    TemplateFit::Warn => println!(
+                        "Task {}: template needs about {:.2}in, {} of {:.2}in will clip it",
+                        self.id, height, field, margin
+                    ),
+                    TemplateFit::Error => {
+                        return Err(PdfError::Validation(vec![ValidationError::new(
+                            field,
+                            format!(
+                                "{:.2}in is too small for the template, which needs about {:.2}in",
+                                margin, height
+                            ),
+                        )])
+                        .into());
+                    }
+                    TemplateFit::Grow => *margin = height,
+                }
+            }
+        }
 
         let mut pdf_params = PrintToPdfParams::builder()
             .print_background(p.print_background)
-            .landscape(p.landscape)
+            .landscape(landscape)
             .display_header_footer(display_header_footer)
-            .margin_top(p.margin_top.unwrap_or(0.0))
-            .margin_right(p.margin_right.unwrap_or(0.0))
-            .margin_bottom(p.margin_bottom.unwrap_or(0.0))
-            .margin_left(p.margin_left.unwrap_or(0.0));
-
-        // Handle dimensions
-        if let (Some(_w), Some(_h)) = (&p.width, &p.height) {
-            // TODO: Parse width/height strings to f64 if they include units
-            // For now, use format-based dimensions
-            let (w, h) = format_to_inches(p.format.as_deref().unwrap_or("A4"));
-            pdf_params = pdf_params.paper_width(w).paper_height(h);
-        } else {
-            let (w, h) = format_to_inches(p.format.as_deref().unwrap_or("A4"));
-            pdf_params = pdf_params.paper_width(w).paper_height(h);
-        }
+            .margin_top(margin_top)
+            .margin_right(margin_right)
+            .margin_bottom(margin_bottom)
+            .margin_left(margin_left)
+            .paper_width(paper_width)
+            .paper_height(paper_height);
 
         // Optional fields
         if let Some(ranges) = &p.print_range {
             pdf_params = pdf_params.page_ranges(ranges.clone());
         }
-        if let Some(header) = &p.header_template {
-            pdf_params = pdf_params.header_template(header.clone());
+        if let Some(scale) = p.scale {
+            pdf_params = pdf_params.scale(scale);
+        }
+        if let Some(header) = header_template {
+            pdf_params = pdf_params.header_template(header);
+        }
+        if let Some(footer) = footer_template {
+            pdf_params = pdf_params.footer_template(footer);
+        }
+
+        let html = if p.return_html == Some(true) {
+            let html: String = page
+                .evaluate("document.documentElement.outerHTML")
+                .await
+                .wrap_err("Failed to capture the rendered HTML")?
+                .into_value()
+                .wrap_err("Rendered HTML is not a string")?;
+            vec![html]
+        } else {
+            Vec::new()
+        };
+
+        if let Some(console_errors) = console_errors {
+            let errors = console_errors.errors();
+            if !errors.is_empty() {
+                return Err(PdfError::PageErrors(errors).into());
+            }
         }
-        if let Some(footer) = &p.footer_template {
-            pdf_params = pdf_params.footer_template(footer.clone());
+        if let Some(resource_errors) = resource_errors {
+            let errors = resource_errors.errors();
+            if !errors.is_empty() {
+                return Err(PdfError::ResourceErrors(errors).into());
+            }
         }
 
         // Generate PDF
-        let pdf_bytes = ctx
-            .page
-            .pdf(pdf_params.build())
+        self.progress.report(RenderEvent::Printing);
+        let debug = p.debug == Some(true);
+        let format = p.output_format.unwrap_or_default();
+        let print = async {
+            if format.is_image() {
+                let mut params = ScreenshotParams::builder()
+                    .format(if format == OutputFormat::Jpeg {
+                        CaptureScreenshotFormat::Jpeg
+                    } else {
+                        CaptureScreenshotFormat::Png
+                    })
+                    .full_page(p.full_page.unwrap_or(true));
+                if let Some(quality) = p.quality {
+                    params = params.quality(quality);
+                }
+                page.screenshot(params.build())
+                    .await
+                    .wrap_err("Failed to capture a screenshot")
+                    .map(|image| (image, None))
+            } else if debug || p.extra_pdf_params.is_some() {
+                print_raw(page, pdf_params.build(), p.extra_pdf_params.as_ref())
+                    .await
+                    .map(|(pdf, info)| (pdf, debug.then_some(info)))
+            } else {
+                page.pdf(pdf_params.build())
+                    .await
+                    .wrap_err("Failed to generate PDF")
+                    .map(|pdf| (pdf, None))
+            }
+        };
+        let started = tokio::time::Instant::now();
+        let print_deadline = self.print_timeout.map_or(self.deadline, |timeout| {
+            self.deadline.min(started + timeout)
+        });
+        let (pdf_bytes, print_info) = tokio::time::timeout_at(print_deadline, print)
+            .instrument(tracing::info_span!(
+                "render_step",
+                step = "generating the PDF"
+            ))
             .await
-            .wrap_err("Failed to generate PDF")?;
+            .map_err(|_| PdfError::PrintTimeout(started.elapsed()))??;
 
-        Ok(pdf_bytes)
+        Ok(PdfOutput {
+            pdf: pdf_bytes,
+            truncated: !ready,
+            html,
+            print: print_info.into_iter().collect(),
+            format,
+            ..PdfOutput::default()
+        })
     }
 }
 
 impl Task<ChromeTaskCtx> for ChromeTask {
-    type Result = Result<Vec<u8>>;
+    type Result = Result<PdfOutput>;
+
+    fn is_failure(result: &Self::Result) -> bool {
+        result.is_err()
+    }
 
     async fn process(&self, ctx: &mut ChromeTaskCtx) -> Self::Result {
-        match self.process_inner(ctx).await {
+        self.progress.report(RenderEvent::Started);
+
+        let shared = Arc::clone(&ctx.shared);
+        let _lease = shared.lease();
+
+        ctx.ensure_page().await?;
+
+        match self.render(ctx).await {
             Ok(result) => Ok(result),
             Err(e) => {
-                // Attempt recovery by recreating page
-                if ctx.recreate_page().await.is_ok() {
+                // Attempt recovery by recreating page, unless there is no time left to retry
+                // or the failure lies with the target itself, which a fresh page won't fix
+                let target_failed = matches!(
+                    e.downcast_ref::<PdfError>(),
+                    Some(
+                        PdfError::Validation(_)
+                            | PdfError::Navigation { .. }
+                            | PdfError::PageErrors(_)
+                            | PdfError::ResourceErrors(_)
+                    )
+                ) || e.downcast_ref::<TargetIsPdf>().is_some();
+                if matches!(
+                    e.downcast_ref::<PdfError>(),
+                    Some(PdfError::BadContent(_) | PdfError::PrintTimeout(_))
+                ) {
+                    // Bad HTML and layouts too heavy to print fail the same way every time,
+                    // but they may leave the page stuck, so the next task still needs a fresh one
+                    let _ = ctx.recreate_page().await;
+                    Err(e)
+                } else if self.recover
+                    && !target_failed
+                    && tokio::time::Instant::now() < self.deadline
+                    && ctx.recreate_page().await.is_ok()
+                {
                     // Retry once with fresh page
-                    self.process_inner(ctx).await
+                    self.render(ctx).await
                 } else {
                     Err(e)
                 }
@@ -258,35 +2804,431 @@ impl Task<ChromeTaskCtx> for ChromeTask {
     }
 }
 
+/// Settings for `ChromeDriver::new`
+#[derive(Debug, Clone)]
+pub struct ChromeDriverConfig {
+    /// Budget for a whole render, queueing included
+    pub task_timeout: Duration,
+    /// Close the browser once no task has run for this long, relaunching it on demand.
+    /// Trades a cold start for idle resources.
+    pub idle_shutdown: Option<Duration>,
+    /// Start workers one at a time this far apart, instead of creating all pages at once
+    pub worker_stagger: Duration,
+    /// Retry failed renders once on a fresh page. Turn off to see the first error
+    /// when debugging, rather than whatever the retry ran into.
+    pub recovery: bool,
+    /// How many pages may be created at once, e.g. when the whole pool recovers together
+    pub page_creation_limit: usize,
+    /// Last resort for PDFs above a payload's `max_output_bytes`
+    pub pdf_optimizer: Option<Optimizer>,
+    /// Largest `html` accepted, checked before the payload is queued
+    pub max_html_bytes: usize,
+    /// Whether renders beyond the pool's capacity wait or are rejected
+    pub backpressure: Backpressure,
+    /// Margins for payloads that leave them out, by format (case-insensitive).
+    /// Formats missing here get no margin.
+    pub format_margins: HashMap<String, Margins>,
+    /// Bounds for `scale`, tighter than the validation any payload goes through
+    pub scale_policy: ScalePolicy,
+    /// Run Chrome at this nice value (Unix only), so rendering yields to other services
+    pub browser_nice: Option<i32>,
+    /// How long a single CDP command may take before it fails; chromiumoxide's default
+    /// when unset. Raise it for slow hosts, lower it so stuck commands fail sooner.
+    pub cdp_timeout: Option<Duration>,
+    /// Shorten the waits of renders queued while the pool is backed up, see `AdaptiveWaits`
+    pub adaptive_waits: Option<AdaptiveWaits>,
+    /// Reject payloads estimated to take too long before they queue, see `AdmissionPolicy`
+    pub admission: Option<AdmissionPolicy>,
+    /// Fail with `PdfError::PrintTimeout` once Chrome has spent this long printing.
+    /// The task deadline applies either way.
+    pub print_timeout: Option<Duration>,
+    /// Connect to the browser at this DevTools WebSocket URL instead of launching one
+    pub browser_ws: Option<String>,
+    /// Fail to start unless the browser is this version, e.g. `126` or `126.0.6478`
+    pub chrome_version: Option<String>,
+    /// Chrome executable to launch, replacing chromiumoxide's detection
+    pub chrome_path: Option<PathBuf>,
+}
+
+impl Default for ChromeDriverConfig {
+    fn default() -> Self {
+        Self {
+            task_timeout: Duration::from_secs(30),
+            idle_shutdown: None,
+            worker_stagger: Duration::ZERO,
+            recovery: true,
+            // One per worker
+            page_creation_limit: 4,
+            pdf_optimizer: None,
+            max_html_bytes: DEFAULT_MAX_HTML_BYTES,
+            backpressure: Backpressure::default(),
+            format_margins: default_format_margins(),
+            scale_policy: ScalePolicy::default(),
+            browser_nice: None,
+            cdp_timeout: None,
+            print_timeout: None,
+            adaptive_waits: None,
+            admission: None,
+            browser_ws: None,
+            chrome_version: None,
+            chrome_path: None,
+        }
+    }
+}
+
 pub struct ChromeDriver {
     pool: WorkerPool<ChromeTaskCtx, ChromeTask>,
-    _shared_browser: SharedBrowser,
+    shared: Arc<SharedBrowser>,
+    /// Fetches URLs that turn out to be PDFs
+    http: reqwest::Client,
     task_timeout: Duration,
+    default_mode: RenderMode,
+    trial: bool,
+    recovery: bool,
+    pdf_optimizer: Option<Optimizer>,
+    max_html_bytes: usize,
+    /// `ChromeDriverConfig::format_margins` keyed by uppercase format
+    format_margins: HashMap<String, Margins>,
+    scale_policy: ScalePolicy,
+    print_timeout: Option<Duration>,
+    adaptive_waits: Option<AdaptiveWaits>,
+    admission: Option<AdmissionPolicy>,
 }
 
 impl ChromeDriver {
-    pub async fn new(task_timeout: Duration) -> Result<Self> {
-        let shared_browser = SharedBrowser::launch().await?;
-        let browser = shared_browser.browser();
+    /// Launch the browser and its worker pool
+    pub async fn new(config: ChromeDriverConfig) -> Result<Self> {
+        let launch_options = LaunchOptions {
+            nice: config.browser_nice,
+            cdp_timeout: config.cdp_timeout,
+            browser_ws: config.browser_ws.clone(),
+            chrome_version: config.chrome_version.clone(),
+            chrome_path: config.chrome_path.clone(),
+        };
+        let shared_browser =
+            Arc::new(SharedBrowser::launch(config.page_creation_limit, launch_options).await?);
 
-        let pool = WorkerPool::new(30, 4, move || {
-            let browser = Arc::clone(&browser);
-            async move { ChromeTaskCtx::new(browser).await }
-        });
+        if let Some(idle_timeout) = config.idle_shutdown {
+            let shared = Arc::downgrade(&shared_browser);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    let Some(shared) = shared.upgrade() else {
+                        break;
+                    };
+                    shared.shutdown_if_idle(idle_timeout).await;
+                }
+            });
+        }
+
+        let shared = Arc::clone(&shared_browser);
+        let pool = WorkerPool::new_staggered(30, 4, config.worker_stagger, move || {
+            let shared = Arc::clone(&shared_browser);
+            async move { ChromeTaskCtx::new(shared).await }
+        })
+        .with_backpressure(config.backpressure);
 
         Ok(Self {
             pool,
-            _shared_browser: shared_browser,
-            task_timeout,
+            shared,
+            http: reqwest::Client::new(),
+            task_timeout: config.task_timeout,
+            default_mode: RenderMode::default(),
+            trial: false,
+            recovery: config.recovery,
+            pdf_optimizer: config.pdf_optimizer,
+            max_html_bytes: config.max_html_bytes,
+            format_margins: config
+                .format_margins
+                .into_iter()
+                .map(|(format, margins)| (format.to_uppercase(), margins))
+                .collect(),
+            scale_policy: config.scale_policy,
+            print_timeout: config.print_timeout,
+            adaptive_waits: config.adaptive_waits,
+            admission: config.admission,
         })
     }
+
+    /// Stamp a trial notice into the footer of every PDF; payloads can't turn it off
+    pub fn with_trial(mut self, trial: bool) -> Self {
+        self.trial = trial;
+        self
+    }
+
+    /// Render mode for payloads that don't choose one.
+    ///
+    /// With `RenderMode::Untrusted`, payloads can't ask for trusted rendering either,
+    /// so a deployment open to the public can't be talked out of its isolation.
+    pub fn with_default_mode(mut self, mode: RenderMode) -> Self {
+        self.default_mode = mode;
+        self
+    }
+
+    /// Margins for the sides `payload` leaves out, from its format or A4 without one
+    fn default_margins(&self, payload: &ChromeDriverPdfPayload) -> Margins {
+        let format = payload.format.as_deref().unwrap_or("A4").to_uppercase();
+        self.format_margins
+            .get(&format)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Download the PDF at `url` as is, checking that it is one.
+    ///
+    /// The request comes from the server rather than the browser, so cookies from `login`
+    /// are not sent along. Password-protected PDFs pass through unless post-processed.
+    async fn fetch_pdf(&self, url: &str, deadline: tokio::time::Instant) -> Result<Vec<u8>> {
+        let fetch = async {
+            let response = self.http.get(url).send().await?.error_for_status()?;
+            response.bytes().await
+        };
+        let pdf = tokio::time::timeout_at(deadline, fetch)
+            .await
+            .wrap_err_with(|| format!("Deadline exceeded while downloading {}", url))?
+            .map_err(|e| PdfError::Navigation {
+                url: url.to_string(),
+                reason: e.to_string(),
+            })?;
+
+        if !pdf.starts_with(b"%PDF-") {
+            return Err(PdfError::Navigation {
+                url: url.to_string(),
+                reason: "served as application/pdf, but is not a PDF".to_string(),
+            }
+            .into());
+        }
+        Ok(pdf.to_vec())
+    }
+
+    /// Finish queued renders and stop the workers
+    pub async fn shutdown(self) {
+        self.pool.shutdown().await;
+    }
+
+    /// Shrink `pdf` until it is at most `limit` bytes, trying the cheapest step first
+    async fn fit_to_size(&self, pdf: Vec<u8>, limit: usize) -> Result<Vec<u8>> {
+        if pdf.len() <= limit {
+            return Ok(pdf);
+        }
+
+        let mut pdf = tokio::task::spawn_blocking(move || compress(&pdf))
+            .await
+            .wrap_err("Compression panicked")??;
+
+        if pdf.len() > limit
+            && let Some(optimizer) = &self.pdf_optimizer
+        {
+            pdf = optimizer.run(&pdf).await?;
+        }
+
+        if pdf.len() > limit {
+            return Err(PdfError::OutputTooLarge {
+                size: pdf.len(),
+                limit,
+            }
+            .into());
+        }
+        Ok(pdf)
+    }
 }
 
 impl PdfDriver for ChromeDriver {
     type Payload = ChromeDriverPdfPayload;
 
-    async fn pdf(&self, payload: Self::Payload) -> Result<Vec<u8>> {
-        let task = ChromeTask::new(payload);
-        self.pool.queue(task, self.task_timeout).await.flatten()
+    async fn pdf(&self, payload: Self::Payload) -> Result<PdfOutput> {
+        self.pdf_with_progress(payload, Progress::default()).await
+    }
+
+    fn stats(&self) -> Option<PoolStats> {
+        Some(self.pool.stats())
+    }
+
+    /// Renders of a remote page, or depending on a session or on the page's own signals,
+    /// can differ each time
+    fn is_cacheable(&self, payload: &Self::Payload) -> bool {
+        payload.url.is_none()
+            && payload.url_template.is_none()
+            && payload.login.is_none()
+            && !payload.wait_for_event
+            && payload.best_effort != Some(true)
+    }
+
+    /// Load `url` and everything it pulls in once, so renders of related pages find the
+    /// shared CSS, scripts and fonts in Chrome's HTTP cache.
+    ///
+    /// Only trusted renders share that cache; isolated ones start from an empty one.
+    async fn prime_cache(&self, url: &str) -> Result<()> {
+        // Its renders don't share the cache, and the server shouldn't browse for its clients
+        if self.default_mode == RenderMode::Untrusted {
+            return Err(eyre!("Cache priming is off while rendering untrusted"));
+        }
+        let _lease = self.shared.lease();
+        let (page, _) = self.shared.new_page().await?;
+
+        let result = tokio::time::timeout(self.task_timeout, async {
+            navigate(&page, url).await?;
+            wait_for_network_idle(&page, NetworkIdleKind::Idle0).await
+        })
+        .await
+        .map_err(|_| eyre!("Timed out priming the cache with {}", url))
+        .flatten();
+
+        if let Err(e) = page.close().await {
+            eprintln!("Failed to close cache priming page: {:?}", e);
+        }
+        result
+    }
+
+    fn capabilities(&self) -> serde_json::Value {
+        serde_json::json!({
+            "formats": PAPER_FORMATS.iter().map(|(name, _, _)| name).collect::<Vec<_>>(),
+            "waitStrategies": [
+                "waitForResources",
+                "waitForEvent",
+                "waitForExpression",
+                "waitForAnimations",
+                "waitForDomStable",
+            ],
+            "mediaFeatures": MEDIA_FEATURES,
+            "outputFormats": [OutputFormat::Pdf, OutputFormat::Png, OutputFormat::Jpeg],
+            "devices": devices::DEVICES.iter().map(|device| device.name).collect::<Vec<_>>(),
+            "markdownThemes": markdown::THEMES.iter().map(|theme| theme.name).collect::<Vec<_>>(),
+            "maxHtmlBytes": self.max_html_bytes,
+            "defaultRenderMode": self.default_mode,
+            "trial": self.trial,
+            "pdfOptimizer": self.pdf_optimizer.is_some(),
+            "scale": {
+                "min": self.scale_policy.min,
+                "max": self.scale_policy.max,
+                "clamp": self.scale_policy.clamp,
+            },
+        })
+    }
+
+    async fn pdf_with_progress(
+        &self,
+        mut payload: Self::Payload,
+        progress: Progress,
+    ) -> Result<PdfOutput> {
+        if self.default_mode == RenderMode::Untrusted
+            && payload.render_mode == Some(RenderMode::Trusted)
+        {
+            return Err(PdfError::Validation(vec![ValidationError::new(
+                "renderMode",
+                "trusted rendering is disabled on this server",
+            )])
+            .into());
+        }
+        payload.render_mode.get_or_insert(self.default_mode);
+        payload.validate()?;
+        if let Some(admission) = &self.admission {
+            admission.check(&payload)?;
+        }
+        if let Some(scale) = payload.scale {
+            payload.scale = Some(self.scale_policy.apply(scale)?);
+        }
+        if let Some(markdown) = payload.markdown.take() {
+            let theme = payload
+                .theme
+                .as_deref()
+                .and_then(markdown::find_theme)
+                .unwrap_or(&markdown::THEMES[0]);
+            payload.html = Some(markdown::to_html(&markdown, theme)?);
+        }
+        if let Some(html) = &payload.html
+            && html.len() > self.max_html_bytes
+        {
+            return Err(PdfError::Validation(vec![ValidationError::new(
+                "html",
+                format!(
+                    "{} bytes is more than the limit of {} bytes",
+                    html.len(),
+                    self.max_html_bytes
+                ),
+            )])
+            .into());
+        }
+
+        // The budget covers queueing and every render step, not each of them separately
+        let deadline = tokio::time::Instant::now() + self.task_timeout;
+
+        let default_margins = self.default_margins(&payload);
+        let post_process = payload.post_process(default_margins)?;
+        let max_output_bytes = payload.max_output_bytes;
+        let return_meta = payload.return_meta == Some(true);
+        let fail_on_blank = payload.fail_on_blank == Some(true);
+        let detect_blank = fail_on_blank || payload.detect_blank == Some(true);
+        let recover = self.recovery && payload.debug != Some(true);
+        let single_url = payload.url.is_some();
+        let image = payload.output_format.is_some_and(OutputFormat::is_image);
+        let wait_budget = self.adaptive_waits.map_or(WaitBudget::FULL, |adaptive| {
+            adaptive.budget(&self.pool.stats())
+        });
+        let task = ChromeTask::new(
+            payload,
+            deadline,
+            progress,
+            TaskOptions {
+                trial: self.trial,
+                recover,
+                default_margins,
+                print_timeout: self.print_timeout,
+                wait_budget,
+            },
+        );
+        let queue_position = self.pool.queue_position();
+        let mut output = match self.pool.queue_until(task, deadline).await.flatten() {
+            Ok(output) => output,
+            // Nothing to print, but the PDF still goes through the post-processing below
+            Err(e) => match e.downcast_ref::<TargetIsPdf>() {
+                Some(target) if single_url && !image => {
+                    PdfOutput::new(self.fetch_pdf(&target.url, deadline).await?)
+                }
+                Some(target) => {
+                    return Err(PdfError::Navigation {
+                        url: target.url.clone(),
+                        reason: if image {
+                            "is a PDF, which can't be captured as an image"
+                        } else {
+                            "is a PDF, which can't be merged with rendered pages"
+                        }
+                        .to_string(),
+                    }
+                    .into());
+                }
+                None => return Err(e),
+            },
+        };
+        output.queue_position = Some(queue_position);
+
+        if !image && !post_process.is_empty() {
+            output.pdf = tokio::task::spawn_blocking(move || post_process.apply(output.pdf))
+                .await
+                .wrap_err("Post-processing panicked")??;
+        }
+        if let Some(limit) = max_output_bytes {
+            output.pdf = self.fit_to_size(output.pdf, limit).await?;
+        }
+        if detect_blank {
+            let pdf = output.pdf.clone();
+            let blank = tokio::task::spawn_blocking(move || looks_blank(&pdf))
+                .await
+                .wrap_err("Blank detection panicked")??;
+            if blank && fail_on_blank {
+                return Err(PdfError::Blank.into());
+            }
+            output.blank = Some(blank);
+        }
+        if return_meta {
+            let pdf = output.pdf.clone();
+            output.meta = Some(
+                tokio::task::spawn_blocking(move || PdfMeta::from_pdf(&pdf))
+                    .await
+                    .wrap_err("Reading page metadata panicked")??,
+            );
+        }
+        Ok(output)
     }
 }
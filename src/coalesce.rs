@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{Report, Result, eyre};
+use futures::FutureExt;
+use futures::future::{BoxFuture, Shared};
+use tracing::Instrument;
+
+use crate::error::PdfError;
+use crate::output::PdfOutput;
+use crate::registry::{DriverPayload, DriverRegistry};
+use crate::worker::PoolFull;
+
+type SharedRender = Shared<BoxFuture<'static, Result<Arc<PdfOutput>, Arc<Report>>>>;
+
+/// Shares one render between identical payloads requested while it is in flight.
+///
+/// Renders run in a task of their own, so the requests waiting on one can come and go
+/// without cancelling it.
+#[derive(Default)]
+pub struct Coalescer {
+    in_flight: Arc<Mutex<HashMap<String, SharedRender>>>,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render `payload`, or wait for the render already in flight under `key`.
+    ///
+    /// `key` must identify the output completely, e.g. the payload's ETag; only
+    /// cacheable payloads should be coalesced.
+    pub async fn pdf(
+        &self,
+        registry: Arc<DriverRegistry>,
+        key: String,
+        payload: DriverPayload,
+    ) -> Result<Arc<PdfOutput>> {
+        let render = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(render) => render.clone(),
+                None => {
+                    let retire = (Arc::clone(&self.in_flight), key.clone());
+                    let task = tokio::spawn(
+                        async move {
+                            let result = registry.pdf(payload).await.map(Arc::new);
+                            // Requests from now on render anew
+                            let (in_flight, key) = retire;
+                            in_flight.lock().unwrap().remove(&key);
+                            result
+                        }
+                        .instrument(tracing::Span::current()),
+                    );
+                    let render: SharedRender = async move {
+                        task.await
+                            .unwrap_or_else(|e| Err(eyre!("Render panicked: {}", e)))
+                            .map_err(Arc::new)
+                    }
+                    .boxed()
+                    .shared();
+                    in_flight.insert(key.clone(), render.clone());
+                    render
+                }
+            }
+        };
+
+        render.await.map_err(|e| shared_error(&e))
+    }
+}
+
+/// A copy of an error every waiter on a render gets, keeping the causes callers check for
+fn shared_error(error: &Report) -> Report {
+    if let Some(error) = error.downcast_ref::<PdfError>() {
+        return error.clone().into();
+    }
+    if error.downcast_ref::<PoolFull>().is_some() {
+        return PoolFull.into();
+    }
+    eyre!("{:#}", error)
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result, eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::chrome::{
+    AdaptiveWaits, AdmissionPolicy, ChromeDriverConfig, DEFAULT_MAX_HTML_BYTES, Margins,
+    RenderMode, ScalePolicy, default_format_margins,
+};
+use crate::postprocess::Optimizer;
+use crate::worker::Backpressure;
+
+/// Default for `max_pending`
+pub const DEFAULT_MAX_PENDING: usize = 100;
+
+/// Default for `job_ttl_secs`
+const DEFAULT_JOB_TTL_SECS: u64 = 3600;
+
+/// Server configuration, from a TOML file with `PDFAN_*` env vars on top.
+///
+/// Every key is optional and named after its env var, so `PDFAN_MAX_PENDING` is
+/// `max_pending`. Keys the server doesn't know are rejected rather than ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Close the browser after this many idle seconds, relaunching on demand
+    pub browser_idle_timeout_secs: Option<u64>,
+    /// `untrusted` isolates every render and stops payloads from opting out
+    pub render_mode: Option<RenderMode>,
+    /// Create worker pages one at a time, this many milliseconds apart
+    pub worker_stagger_ms: Option<u64>,
+    /// Cap on pages created at once, throttling recovery storms
+    pub page_creation_limit: Option<usize>,
+    /// Cap on the size of HTML and Typst markup in payloads
+    pub max_html_bytes: Option<usize>,
+    /// Default margins by format, replacing the built-in ones of the formats listed.
+    ///
+    /// `PDFAN_FORMAT_MARGINS` names a JSON file of them instead, whose formats win.
+    pub format_margins: HashMap<String, Margins>,
+    /// Operator bounds for `scale`
+    pub min_scale: Option<f64>,
+    pub max_scale: Option<f64>,
+    /// `clamp` brings out-of-range scales within bounds instead of rejecting them
+    pub scale_out_of_range: Option<ScaleOutOfRange>,
+    /// Nice value for Chrome on shared hosts, e.g. 10
+    pub browser_nice: Option<i32>,
+    /// Cap on Chrome's print step alone, so pathological layouts fail with their own error
+    pub print_timeout_ms: Option<u64>,
+    /// Per-command CDP timeout, e.g. raised on slow hosts
+    pub cdp_timeout_ms: Option<u64>,
+    /// Shorten waits while renders queue up, never below this floor
+    pub adaptive_wait_floor_ms: Option<u64>,
+    /// Reject payloads estimated to take longer than `budget_ms`, weighing their features
+    /// by `[admission.costs]`. `PDFAN_ADMISSION_BUDGET_MS` sets the budget.
+    pub admission: Option<AdmissionPolicy>,
+    /// Debugging aid: report the first render error instead of retrying.
+    /// `PDFAN_NO_RECOVERY` sets it when present.
+    pub no_recovery: bool,
+    /// `reject` answers 503 right away when every slot is taken, instead of queueing
+    pub backpressure: Option<String>,
+    /// Command used to shrink PDFs above `maxOutputBytes`, see `Optimizer`
+    pub pdf_optimizer: Option<String>,
+    pub trial: bool,
+    /// Named payload defaults, one `<name>.json` per template
+    pub templates_dir: Option<PathBuf>,
+    /// Retry renders the browser fails on with a second browser, with its own pool
+    pub fallback_chrome: bool,
+    /// DevTools WebSocket URL of a running browser to use instead of launching one
+    pub browser_ws: Option<String>,
+    /// Refuse to start with any other browser version, e.g. `126` or `126.0.6478`
+    pub chrome_version: Option<String>,
+    /// Chrome executable, when chromiumoxide doesn't find it
+    pub chrome_path: Option<PathBuf>,
+    /// Finished jobs stay available for polling this long
+    pub job_ttl_secs: Option<u64>,
+    /// Renders queued, waiting or running across drivers beyond which requests get a 503
+    pub max_pending: Option<usize>,
+}
+
+/// What to do with a payload `scale` outside the operator's bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleOutOfRange {
+    Reject,
+    Clamp,
+}
+
+impl FromStr for ScaleOutOfRange {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "clamp" => Ok(Self::Clamp),
+            _ => Err(eyre!("expected reject or clamp")),
+        }
+    }
+}
+
+impl Config {
+    /// Read the TOML file at `path`, if any, then apply the env vars set
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read config {}", path.display()))?;
+        toml::from_str(&file).wrap_err_with(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// Replace file values with the `PDFAN_*` env vars that are set
+    pub fn apply_env(&mut self) -> Result<()> {
+        env(
+            "PDFAN_BROWSER_IDLE_TIMEOUT_SECS",
+            &mut self.browser_idle_timeout_secs,
+        )?;
+        env("PDFAN_RENDER_MODE", &mut self.render_mode)?;
+        env("PDFAN_WORKER_STAGGER_MS", &mut self.worker_stagger_ms)?;
+        env("PDFAN_PAGE_CREATION_LIMIT", &mut self.page_creation_limit)?;
+        env("PDFAN_MAX_HTML_BYTES", &mut self.max_html_bytes)?;
+        if let Some(path) = std::env::var_os("PDFAN_FORMAT_MARGINS") {
+            let file = std::fs::read(&path).wrap_err("Failed to read PDFAN_FORMAT_MARGINS")?;
+            let margins: HashMap<String, Margins> =
+                serde_json::from_slice(&file).wrap_err("Invalid PDFAN_FORMAT_MARGINS file")?;
+            self.format_margins.extend(margins);
+        }
+        env("PDFAN_MIN_SCALE", &mut self.min_scale)?;
+        env("PDFAN_MAX_SCALE", &mut self.max_scale)?;
+        env("PDFAN_SCALE_OUT_OF_RANGE", &mut self.scale_out_of_range)?;
+        env("PDFAN_BROWSER_NICE", &mut self.browser_nice)?;
+        env("PDFAN_PRINT_TIMEOUT_MS", &mut self.print_timeout_ms)?;
+        env("PDFAN_CDP_TIMEOUT_MS", &mut self.cdp_timeout_ms)?;
+        env(
+            "PDFAN_ADAPTIVE_WAIT_FLOOR_MS",
+            &mut self.adaptive_wait_floor_ms,
+        )?;
+        let mut admission_budget = None;
+        env("PDFAN_ADMISSION_BUDGET_MS", &mut admission_budget)?;
+        if let Some(budget_ms) = admission_budget {
+            self.admission
+                .get_or_insert_with(Default::default)
+                .budget_ms = budget_ms;
+        }
+        self.no_recovery |= std::env::var_os("PDFAN_NO_RECOVERY").is_some();
+        env("PDFAN_BACKPRESSURE", &mut self.backpressure)?;
+        env("PDFAN_PDF_OPTIMIZER", &mut self.pdf_optimizer)?;
+        self.trial |= std::env::var_os("PDFAN_TRIAL").is_some();
+        env("PDFAN_TEMPLATES_DIR", &mut self.templates_dir)?;
+        self.fallback_chrome |= std::env::var_os("PDFAN_FALLBACK_CHROME").is_some();
+        env("PDFAN_BROWSER_WS", &mut self.browser_ws)?;
+        env("PDFAN_CHROME_VERSION", &mut self.chrome_version)?;
+        env("PDFAN_CHROME_PATH", &mut self.chrome_path)?;
+        env("PDFAN_JOB_TTL_SECS", &mut self.job_ttl_secs)?;
+        env("PDFAN_MAX_PENDING", &mut self.max_pending)?;
+        Ok(())
+    }
+
+    /// Reject values that parse but make no sense together
+    pub fn validate(&self) -> Result<()> {
+        let scale = self.scale_policy();
+        if scale.min > scale.max {
+            return Err(eyre!(
+                "min_scale {} is above max_scale {}",
+                scale.min,
+                scale.max
+            ));
+        }
+        if self
+            .admission
+            .is_some_and(|admission| admission.budget_ms == 0)
+        {
+            return Err(eyre!("admission needs a budget_ms above 0"));
+        }
+        if self.page_creation_limit == Some(0) {
+            return Err(eyre!("page_creation_limit must be at least 1"));
+        }
+        if let Some(backpressure) = self.backpressure.as_deref()
+            && !matches!(backpressure, "wait" | "reject")
+        {
+            return Err(eyre!(
+                "unknown backpressure `{}`, expected wait or reject",
+                backpressure
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn scale_policy(&self) -> ScalePolicy {
+        let default = ScalePolicy::default();
+        ScalePolicy {
+            min: self.min_scale.unwrap_or(default.min),
+            max: self.max_scale.unwrap_or(default.max),
+            clamp: self.scale_out_of_range == Some(ScaleOutOfRange::Clamp),
+        }
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode.unwrap_or_default()
+    }
+
+    pub fn max_html_bytes(&self) -> usize {
+        self.max_html_bytes.unwrap_or(DEFAULT_MAX_HTML_BYTES)
+    }
+
+    /// The settings that change what a payload renders to, the same across restarts,
+    /// so cached renders can be told apart from those of a differently configured server
+    pub fn render_fingerprint(&self) -> String {
+        let format_margins: BTreeMap<_, _> = self.format_margins.iter().collect();
+        serde_json::json!({
+            "formatMargins": format_margins,
+            "minScale": self.min_scale,
+            "maxScale": self.max_scale,
+            "scaleOutOfRange": self.scale_out_of_range,
+            "pdfOptimizer": self.pdf_optimizer,
+            "renderMode": self.render_mode(),
+            "trial": self.trial,
+        })
+        .to_string()
+    }
+
+    pub fn job_ttl(&self) -> Duration {
+        Duration::from_secs(self.job_ttl_secs.unwrap_or(DEFAULT_JOB_TTL_SECS))
+    }
+
+    pub fn max_pending(&self) -> usize {
+        self.max_pending.unwrap_or(DEFAULT_MAX_PENDING)
+    }
+
+    /// Options for the Chrome driver, and the fallback one when there is one
+    pub fn chrome_driver_config(&self) -> ChromeDriverConfig {
+        let defaults = ChromeDriverConfig::default();
+        let mut format_margins = default_format_margins();
+        format_margins.extend(
+            self.format_margins
+                .iter()
+                .map(|(format, margins)| (format.to_uppercase(), *margins)),
+        );
+
+        ChromeDriverConfig {
+            idle_shutdown: self.browser_idle_timeout_secs.map(Duration::from_secs),
+            worker_stagger: self
+                .worker_stagger_ms
+                .map(Duration::from_millis)
+                .unwrap_or_default(),
+            recovery: !self.no_recovery,
+            page_creation_limit: self
+                .page_creation_limit
+                .unwrap_or(defaults.page_creation_limit),
+            max_html_bytes: self.max_html_bytes(),
+            format_margins,
+            scale_policy: self.scale_policy(),
+            browser_nice: self.browser_nice,
+            print_timeout: self.print_timeout_ms.map(Duration::from_millis),
+            cdp_timeout: self.cdp_timeout_ms.map(Duration::from_millis),
+            adaptive_waits: self.adaptive_wait_floor_ms.map(|ms| AdaptiveWaits {
+                floor: Duration::from_millis(ms),
+            }),
+            admission: self.admission,
+            backpressure: match self.backpressure.as_deref() {
+                Some("reject") => Backpressure::Reject,
+                _ => Backpressure::Wait,
+            },
+            pdf_optimizer: self.pdf_optimizer.as_deref().and_then(Optimizer::parse),
+            browser_ws: self.browser_ws.clone(),
+            chrome_version: self.chrome_version.clone(),
+            chrome_path: self.chrome_path.clone(),
+            ..defaults
+        }
+    }
+}
+
+/// Replace `value` with env var `name` when it is set, naming the var when it doesn't parse
+fn env<T>(name: &str, value: &mut Option<T>) -> Result<()>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    if let Ok(raw) = std::env::var(name) {
+        *value = Some(
+            raw.parse()
+                .map_err(|e| eyre!("Invalid {} `{}`: {}", name, raw, e))?,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+/// Screen and browser of a device to emulate, like Puppeteer's device descriptors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Device {
+    pub name: &'static str,
+    /// Viewport size in CSS pixels
+    pub width: u32,
+    pub height: u32,
+    pub device_scale_factor: f64,
+    /// Emulate a mobile browser: viewport meta tag, overlay scrollbars and so on
+    pub mobile: bool,
+    pub user_agent: &'static str,
+}
+
+/// Built-in devices, looked up by `name`
+pub const DEVICES: &[Device] = &[
+    Device {
+        name: "iphone-13",
+        width: 390,
+        height: 844,
+        device_scale_factor: 3.0,
+        mobile: true,
+        user_agent: "Mozilla/5.0 (iPhone; CPU iPhone OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    },
+    Device {
+        name: "iphone-se",
+        width: 375,
+        height: 667,
+        device_scale_factor: 2.0,
+        mobile: true,
+        user_agent: "Mozilla/5.0 (iPhone; CPU iPhone OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    },
+    Device {
+        name: "ipad",
+        width: 810,
+        height: 1080,
+        device_scale_factor: 2.0,
+        mobile: true,
+        user_agent: "Mozilla/5.0 (iPad; CPU OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    },
+    Device {
+        name: "ipad-pro",
+        width: 1024,
+        height: 1366,
+        device_scale_factor: 2.0,
+        mobile: true,
+        user_agent: "Mozilla/5.0 (iPad; CPU OS 15_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.0 Mobile/15E148 Safari/604.1",
+    },
+    Device {
+        name: "pixel-7",
+        width: 412,
+        height: 915,
+        device_scale_factor: 2.625,
+        mobile: true,
+        user_agent: "Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Mobile Safari/537.36",
+    },
+];
+
+/// The built-in device called `name`, ignoring case
+pub fn find(name: &str) -> Option<&'static Device> {
+    DEVICES
+        .iter()
+        .find(|device| device.name.eq_ignore_ascii_case(name))
+}
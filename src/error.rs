@@ -0,0 +1,85 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// A single problem found while validating a payload
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// Errors with a known cause, so callers can tell bad input apart from rendering failures.
+///
+/// These travel inside `color_eyre` reports and are recovered with `downcast_ref`.
+#[derive(Debug, Clone)]
+pub enum PdfError {
+    /// The payload was rejected before rendering; lists every problem found
+    Validation(Vec<ValidationError>),
+    /// The page could not be loaded at all, e.g. DNS failure or connection refused
+    Navigation { url: String, reason: String },
+    /// The HTML or Typst markup given in the payload wouldn't load, e.g. a script never
+    /// stops running or the markup doesn't compile
+    BadContent(String),
+    /// The page logged errors or threw while rendering, and the payload asked to fail on them
+    PageErrors(Vec<String>),
+    /// Resources the page needed failed to load, and the payload asked to fail on that
+    ResourceErrors(Vec<String>),
+    /// Chrome was still printing the loaded page when the print timeout or deadline passed
+    PrintTimeout(std::time::Duration),
+    /// The PDF seems to have nothing on it, and the payload asked to fail on that
+    Blank,
+    /// The PDF stayed above the requested size, even after shrinking it
+    OutputTooLarge { size: usize, limit: usize },
+}
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdfError::Validation(errors) => {
+                write!(f, "Invalid payload: ")?;
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", error.field, error.message)?;
+                }
+                Ok(())
+            }
+            PdfError::Navigation { url, reason } => {
+                write!(f, "Failed to load `{}`: {}", url, reason)
+            }
+            PdfError::BadContent(reason) => {
+                write!(f, "The content could not be rendered: {}", reason)
+            }
+            PdfError::PageErrors(errors) => {
+                write!(f, "The page reported errors: {}", errors.join("; "))
+            }
+            PdfError::ResourceErrors(urls) => {
+                write!(f, "Resources failed to load: {}", urls.join("; "))
+            }
+            PdfError::PrintTimeout(elapsed) => {
+                write!(f, "PDF generation timed out after {:?}", elapsed)
+            }
+            PdfError::Blank => write!(f, "The rendered PDF looks blank"),
+            PdfError::OutputTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "PDF is {} bytes, above the limit of {} bytes even after shrinking it",
+                    size, limit
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PdfError {}
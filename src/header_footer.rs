@@ -0,0 +1,104 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Chrome falls back to its own date/title/url header or footer when a template is
+/// missing, so an empty element is used to suppress the side that wasn't asked for.
+pub const EMPTY_TEMPLATE: &str = "<span></span>";
+
+/// Margin in inches reserved for a generated header or footer when none was given
+pub const DEFAULT_TEMPLATE_MARGIN: f64 = 0.5;
+
+/// Smallest bottom margin in inches that keeps the trial notice visible
+pub const TRIAL_MARGIN: f64 = 0.4;
+
+const TRIAL_NOTICE: &str = "Generated with pdfan — trial";
+
+/// Append the trial notice below `footer`, or use it alone when there is no footer
+pub fn with_trial_notice(footer: Option<&str>) -> String {
+    format!(
+        r#"<div style="width: 100%;">{}<div style="font-size: 7px; color: #999; text-align: center;">{}</div></div>"#,
+        footer.unwrap_or(""),
+        escape_html(TRIAL_NOTICE)
+    )
+}
+
+/// What to do when a header or footer template looks taller than the margin it prints in,
+/// where Chrome would clip it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateFit {
+    /// Log it and print anyway
+    #[default]
+    Warn,
+    /// Reject the render
+    Error,
+    /// Raise the margin to the template's height
+    Grow,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageNumberPosition {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomCenter,
+    BottomRight,
+}
+
+impl PageNumberPosition {
+    pub fn is_top(self) -> bool {
+        matches!(self, Self::TopLeft | Self::TopCenter | Self::TopRight)
+    }
+
+    fn text_align(self) -> &'static str {
+        match self {
+            Self::TopLeft | Self::BottomLeft => "left",
+            Self::TopCenter | Self::BottomCenter => "center",
+            Self::TopRight | Self::BottomRight => "right",
+        }
+    }
+}
+
+/// Automatic page numbers, rendered into Chrome's header or footer template
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PageNumberSpec {
+    #[serde(default)]
+    pub position: PageNumberPosition,
+    /// Text with `{page}` and `{total}` placeholders, e.g. `Page {page} of {total}`
+    pub format: Option<String>,
+    /// Font size in CSS pixels
+    pub font_size: Option<f64>,
+}
+
+impl PageNumberSpec {
+    const DEFAULT_FORMAT: &str = "Page {page} of {total}";
+    const DEFAULT_FONT_SIZE: f64 = 10.0;
+
+    /// Build the header/footer template HTML.
+    ///
+    /// Chrome fills elements with the `pageNumber` and `totalPages` classes, and
+    /// templates don't inherit page styles, so all styling is inline.
+    pub fn template(&self) -> String {
+        let text = escape_html(self.format.as_deref().unwrap_or(Self::DEFAULT_FORMAT))
+            .replace("{page}", r#"<span class="pageNumber"></span>"#)
+            .replace("{total}", r#"<span class="totalPages"></span>"#);
+
+        format!(
+            r#"<div style="width: 100%; box-sizing: border-box; padding: 0 0.4in; font-size: {}px; text-align: {};">{}</div>"#,
+            self.font_size.unwrap_or(Self::DEFAULT_FONT_SIZE),
+            self.position.text_align(),
+            text
+        )
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
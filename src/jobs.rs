@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Context, Result, eyre};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+use tracing::Instrument;
+
+use crate::output::PdfOutput;
+use crate::progress::{Progress, RenderEvent};
+use crate::registry::{DriverPayload, DriverRegistry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// Something that happened to a job, as streamed to subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum JobEvent {
+    Queued,
+    Progress { step: RenderEvent },
+    Done { truncated: bool },
+    Failed { error: String },
+    Cancelled,
+}
+
+impl JobEvent {
+    /// Whether no events follow this one
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            Self::Done { .. } | Self::Failed { .. } | Self::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub result: Option<Arc<PdfOutput>>,
+    finished_at: Option<Instant>,
+    events: broadcast::Sender<JobEvent>,
+    /// Stops the task rendering the job
+    task: Option<AbortHandle>,
+}
+
+impl Job {
+    fn queued() -> Self {
+        // Room for every render step, so subscribers that keep up never lag
+        let (events, _) = broadcast::channel(16);
+        Self {
+            status: JobStatus::Queued,
+            error: None,
+            result: None,
+            finished_at: None,
+            events,
+            task: None,
+        }
+    }
+
+    /// The event describing where the job is now
+    pub fn current_event(&self) -> JobEvent {
+        match self.status {
+            JobStatus::Queued => JobEvent::Queued,
+            JobStatus::Running => JobEvent::Progress {
+                step: RenderEvent::Started,
+            },
+            JobStatus::Done => JobEvent::Done {
+                truncated: self.result.as_ref().is_some_and(|output| output.truncated),
+            },
+            JobStatus::Failed => JobEvent::Failed {
+                error: self.error.clone().unwrap_or_default(),
+            },
+            JobStatus::Cancelled => JobEvent::Cancelled,
+        }
+    }
+}
+
+/// In-memory store of render jobs submitted without waiting for the result.
+///
+/// Finished jobs are kept for `ttl` so clients can poll for them, then evicted.
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    ttl: Duration,
+}
+
+impl JobStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.evict_expired(&mut jobs);
+        jobs.get(id).cloned()
+    }
+
+    /// The job as it is now, and a receiver for every event after that
+    pub fn subscribe(&self, id: &str) -> Option<(Job, broadcast::Receiver<JobEvent>)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.evict_expired(&mut jobs);
+        let job = jobs.get(id)?;
+        Some((job.clone(), job.events.subscribe()))
+    }
+
+    fn evict_expired(&self, jobs: &mut HashMap<String, Job>) {
+        jobs.retain(|_, job| job.finished_at.is_none_or(|at| at.elapsed() < self.ttl));
+    }
+
+    /// Queue `payload` for rendering and return the job id right away.
+    ///
+    /// When `callback_url` is set, the outcome is POSTed there once the job finishes.
+    /// `warm_url` is loaded first to prime the driver's cache, see `PdfDriver::prime_cache`;
+    /// the job renders either way.
+    pub fn submit(
+        self: &Arc<Self>,
+        registry: Arc<DriverRegistry>,
+        client: reqwest::Client,
+        payload: DriverPayload,
+        callback_url: Option<String>,
+        warm_url: Option<String>,
+    ) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            self.evict_expired(&mut jobs);
+            jobs.insert(id.clone(), Job::queued());
+        }
+
+        let progress = {
+            let store = Arc::downgrade(self);
+            let job_id = id.clone();
+            Progress::new(move |event| {
+                if let Some(store) = store.upgrade() {
+                    store.report(&job_id, event);
+                }
+            })
+        };
+
+        let store = Arc::clone(self);
+        let job_id = id.clone();
+        let span = tracing::info_span!("job", id = %id);
+        let job = async move {
+            if let Some(url) = warm_url
+                && let Err(e) = registry.prime_cache(&payload.driver, &url).await
+            {
+                eprintln!("Failed to prime the cache for job {}: {:?}", job_id, e);
+            }
+            let result = registry.pdf_with_progress(payload, progress).await;
+            let job = store.finish(&job_id, result);
+
+            if let Some(url) = callback_url
+                && let Err(e) = notify(&client, &url, &job_id, &job).await
+            {
+                eprintln!("Failed to deliver callback for job {}: {:?}", job_id, e);
+            }
+        };
+        let task = tokio::spawn(job.instrument(span)).abort_handle();
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.task = Some(task);
+        }
+
+        id
+    }
+
+    /// Cancel the job unless it already finished, returning it as it is afterwards.
+    ///
+    /// A queued job leaves the queue without being rendered. A running one is cancelled on
+    /// a best-effort basis: its result is discarded and no callback is sent, but the browser
+    /// may finish the render in the background.
+    pub fn cancel(&self, id: &str) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.evict_expired(&mut jobs);
+        let job = jobs.get_mut(id)?;
+
+        if !job.status.is_finished() {
+            // Dropping the render drops its place in the worker pool's queue
+            if let Some(task) = &job.task {
+                task.abort();
+            }
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(Instant::now());
+            let _ = job.events.send(JobEvent::Cancelled);
+        }
+        Some(job.clone())
+    }
+
+    fn report(&self, id: &str, step: RenderEvent) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            if step == RenderEvent::Started {
+                job.status = JobStatus::Running;
+            }
+            // Sending fails only when nobody listens
+            let _ = job.events.send(JobEvent::Progress { step });
+        }
+    }
+
+    fn finish(&self, id: &str, result: Result<PdfOutput>) -> Job {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.entry(id.to_string()).or_insert_with(Job::queued);
+        // Cancelled while finishing; the cancellation stands
+        if job.status == JobStatus::Cancelled {
+            return job.clone();
+        }
+
+        match result {
+            Ok(output) => {
+                job.status = JobStatus::Done;
+                job.result = Some(Arc::new(output));
+            }
+            Err(e) => {
+                job.status = JobStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+        job.finished_at = Some(Instant::now());
+        let _ = job.events.send(job.current_event());
+
+        job.clone()
+    }
+}
+
+/// POST the job's outcome to `url`, retrying with backoff.
+///
+/// A finished job sends the PDF itself; a failed one sends `{ id, status, error }` as JSON.
+/// Either way the job id is in the `X-Pdfan-Job-Id` header.
+async fn notify(client: &reqwest::Client, url: &str, id: &str, job: &Job) -> Result<()> {
+    const ATTEMPTS: u32 = 4;
+
+    let (content_type, body, headers) = match &job.result {
+        Some(output) => (
+            output.format.content_type(),
+            output.pdf.clone(),
+            output.headers(),
+        ),
+        None => (
+            "application/json",
+            serde_json::to_vec(&serde_json::json!({
+                "id": id,
+                "status": job.status,
+                "error": job.error,
+            }))?,
+            Vec::new(),
+        ),
+    };
+
+    let mut backoff = Duration::from_secs(1);
+    for attempt in 1..=ATTEMPTS {
+        let mut request = client
+            .post(url)
+            .header("Content-Type", content_type)
+            .header("X-Pdfan-Job-Id", id);
+        for (name, value) in &headers {
+            request = request.header(*name, value);
+        }
+        let response = request.body(body.clone()).send().await;
+
+        match response {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => eprintln!(
+                "Callback for job {} returned {} (attempt {}/{})",
+                id,
+                response.status(),
+                attempt,
+                ATTEMPTS
+            ),
+            Err(e) => eprintln!(
+                "Callback for job {} failed: {} (attempt {}/{})",
+                id, e, attempt, ATTEMPTS
+            ),
+        }
+
+        if attempt < ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    Err(eyre!("Gave up after {} attempts", ATTEMPTS)).wrap_err(format!("Callback to {}", url))
+}
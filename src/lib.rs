@@ -0,0 +1,22 @@
+pub mod chrome;
+pub mod coalesce;
+pub mod config;
+pub mod devices;
+pub mod error;
+pub mod header_footer;
+pub mod jobs;
+pub mod markdown;
+pub mod metrics;
+#[cfg(feature = "test-utils")]
+pub mod mock;
+pub mod output;
+pub mod postprocess;
+#[cfg(unix)]
+pub mod priority;
+pub mod progress;
+pub mod registry;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod typst;
+pub mod wait;
+pub mod worker;
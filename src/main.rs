@@ -1,24 +1,92 @@
-use color_eyre::eyre::Result;
-use std::{sync::Arc, time::Duration};
+use color_eyre::eyre::{Context, Result};
+use std::{convert::Infallible, sync::Arc};
 
 use axum::{
     Json, Router,
-    extract::State,
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::post,
+    extract::{DefaultBodyLimit, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
 };
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::Instrument;
+use tracing_error::ErrorLayer;
+use tracing_subscriber::prelude::*;
 
-use crate::chrome::{ChromeDriver, ChromeDriverPdfPayload, PdfDriver};
+use pdfan::chrome::ChromeDriver;
+use pdfan::coalesce::Coalescer;
+use pdfan::config::Config;
+use pdfan::error::{PdfError, ValidationError};
+use pdfan::jobs::{Job, JobEvent, JobStatus, JobStore};
+use pdfan::output::PdfOutput;
+use pdfan::registry::{DriverPayload, DriverRegistry};
+use pdfan::typst::TypstDriver;
+use pdfan::worker::PoolFull;
 
-pub mod chrome;
-pub mod wait;
-pub mod worker;
+#[derive(Clone)]
+struct AppState {
+    registry: Arc<DriverRegistry>,
+    /// Shares renders between identical cacheable requests in flight at the same time
+    coalescer: Arc<Coalescer>,
+    jobs: Arc<JobStore>,
+    http: reqwest::Client,
+    /// Renders queued, waiting or running across drivers beyond which requests get a 503
+    max_pending: usize,
+    /// Largest request body accepted
+    max_body_bytes: usize,
+    /// `Config::render_fingerprint`, part of every ETag
+    render_fingerprint: Arc<str>,
+}
+
+impl AppState {
+    /// Turn the request away when too much work is pending, before it adds to the queue
+    fn check_capacity(&self) -> Result<(), PoolFull> {
+        let pending: usize = self
+            .registry
+            .stats()
+            .values()
+            .map(|stats| stats.queued + stats.waiting + stats.in_flight)
+            .sum();
+        if pending >= self.max_pending {
+            return Err(PoolFull);
+        }
+        Ok(())
+    }
+}
 
 struct AppError(color_eyre::eyre::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        match self.0.downcast_ref::<PdfError>() {
+            Some(PdfError::Validation(errors)) => {
+                return (StatusCode::BAD_REQUEST, Json(errors)).into_response();
+            }
+            Some(error @ PdfError::Navigation { .. }) => {
+                return (StatusCode::BAD_GATEWAY, error.to_string()).into_response();
+            }
+            Some(
+                error @ (PdfError::OutputTooLarge { .. }
+                | PdfError::BadContent(_)
+                | PdfError::PageErrors(_)
+                | PdfError::ResourceErrors(_)
+                | PdfError::Blank),
+            ) => {
+                return (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()).into_response();
+            }
+            Some(error @ PdfError::PrintTimeout(_)) => {
+                return (StatusCode::GATEWAY_TIMEOUT, error.to_string()).into_response();
+            }
+            None => {}
+        }
+        if self.0.downcast_ref::<PoolFull>().is_some() {
+            return (StatusCode::SERVICE_UNAVAILABLE, self.0.to_string()).into_response();
+        }
+
         (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
     }
 }
@@ -33,28 +101,396 @@ impl<E: Into<color_eyre::eyre::Error>> From<E> for AppError {
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let chrome_driver = Arc::new(
-        ChromeDriver::new(Duration::from_secs(30))
-            .await
-            .expect("Failed to initialize Chrome driver"),
+    // color_eyre's reports, panics included, list the spans of the render or job they hit
+    let subscriber = tracing_subscriber::registry().with(ErrorLayer::default());
+    #[cfg(feature = "otel")]
+    let tracer_provider = pdfan::telemetry::init(subscriber)?;
+    #[cfg(not(feature = "otel"))]
+    subscriber
+        .try_init()
+        .wrap_err("Failed to install tracing subscriber")?;
+
+    let config = Config::load(config_path().as_deref())?;
+    let render_mode = config.render_mode();
+    let chrome_config = config.chrome_driver_config();
+    let chrome_driver = ChromeDriver::new(chrome_config.clone())
+        .await
+        .wrap_err("Failed to initialize Chrome driver")?
+        .with_default_mode(render_mode)
+        .with_trial(config.trial);
+
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", chrome_driver);
+    registry.register(
+        "typst",
+        TypstDriver::new().with_max_content_bytes(config.max_html_bytes()),
     );
 
-    // build our application with a single route
+    if let Some(dir) = &config.templates_dir {
+        let count = registry.load_templates(dir)?;
+        println!("Loaded {} payload templates", count);
+    }
+
+    if config.fallback_chrome {
+        let fallback = ChromeDriver::new(chrome_config)
+            .await
+            .wrap_err("Failed to initialize the fallback Chrome driver")?
+            .with_default_mode(render_mode)
+            .with_trial(config.trial);
+        registry.register("chrome-fallback", fallback);
+        registry.set_fallback("chrome", "chrome-fallback");
+    }
+
+    let state = AppState {
+        registry: Arc::new(registry),
+        coalescer: Arc::new(Coalescer::new()),
+        jobs: Arc::new(JobStore::new(config.job_ttl())),
+        http: reqwest::Client::new(),
+        max_pending: config.max_pending(),
+        // Room for a payload at the html limit, whose JSON escaping may grow it
+        max_body_bytes: config.max_html_bytes().saturating_mul(2),
+        render_fingerprint: config.render_fingerprint().into(),
+    };
+
     let app = Router::new()
         .route("/api/convert", post(handle_pdf))
-        .with_state(chrome_driver);
+        .route("/pdf", post(handle_untagged_pdf))
+        .route("/health", get(handle_health))
+        .route("/jobs", post(handle_submit_job))
+        .route(
+            "/jobs/{id}",
+            get(handle_job_status).delete(handle_cancel_job),
+        )
+        .route("/jobs/{id}/result", get(handle_job_result))
+        .route("/jobs/{id}/events", get(handle_job_events))
+        .route("/admin/pool", get(handle_pool_stats))
+        .route("/capabilities", get(handle_capabilities))
+        .route("/schema", get(handle_schema))
+        .layer(DefaultBodyLimit::max(state.max_body_bytes))
+        .with_state(state);
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
     println!("Listening on port 3000");
     axum::serve(listener, app).await?;
 
+    #[cfg(feature = "otel")]
+    if let Some(provider) = tracer_provider {
+        let _ = provider.shutdown();
+    }
+
     Ok(())
 }
 
+/// Config file from `--config <path>`, or `PDFAN_CONFIG` without it
+fn config_path() -> Option<std::path::PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(Into::into);
+        }
+    }
+    std::env::var_os("PDFAN_CONFIG").map(Into::into)
+}
+
+/// Span covering one render, continuing the caller's trace when exporting traces
+fn render_span(headers: &HeaderMap, driver: &str) -> tracing::Span {
+    let span = tracing::info_span!("render", driver);
+    #[cfg(feature = "otel")]
+    pdfan::telemetry::set_parent(&span, headers);
+    #[cfg(not(feature = "otel"))]
+    let _ = headers;
+    span
+}
+
 async fn handle_pdf(
-    State(driver): State<Arc<ChromeDriver>>,
-    Json(payload): Json<ChromeDriverPdfPayload>,
-) -> Result<Vec<u8>, AppError> {
-    Ok(driver.pdf(payload).await?)
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DriverPayload>,
+) -> Result<Response, AppError> {
+    let payload = state.registry.resolve_template(payload)?;
+    // Identical cacheable payloads render equivalent PDFs on the same settings, so a client
+    // holding one can skip the render
+    let etag = state
+        .registry
+        .is_cacheable(&payload)
+        .then(|| payload.etag(&state.render_fingerprint));
+    if let Some(etag) = &etag
+        && if_none_match(&headers, etag)
+    {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response());
+    }
+
+    state.check_capacity()?;
+    let span = render_span(&headers, &payload.driver);
+    let output = match &etag {
+        Some(etag) => {
+            // The filename only shows in the response, but followers share the leader's
+            let key = format!("{}{:?}{:?}", etag, payload.filename, payload.disposition);
+            state
+                .coalescer
+                .pdf(Arc::clone(&state.registry), key, payload)
+                .instrument(span)
+                .await?
+        }
+        None => Arc::new(state.registry.pdf(payload).instrument(span).await?),
+    };
+    let mut response = pdf_response(&output);
+    if let Some(etag) = etag
+        && let Ok(value) = header::HeaderValue::from_str(&etag)
+    {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    Ok(response)
+}
+
+/// `/pdf`: `/api/convert` for payloads that may leave out `driver`, telling it from their
+/// fields instead: Typst's `content`, or Chrome for anything else
+async fn handle_untagged_pdf(
+    state: State<AppState>,
+    headers: HeaderMap,
+    Json(mut payload): Json<serde_json::Value>,
+) -> Result<Response, AppError> {
+    if let Some(fields) = payload.as_object_mut()
+        && !fields.contains_key("driver")
+        && fields.contains_key("content")
+    {
+        fields.insert("driver".to_string(), "typst".into());
+    }
+    let payload = serde_json::from_value(payload)
+        .map_err(|e| PdfError::Validation(vec![ValidationError::new("payload", e.to_string())]))?;
+    handle_pdf(state, headers, Json(payload)).await
+}
+
+/// Whether the request's `If-None-Match` lists `etag`, weak comparison as for GET
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/"))
+}
+
+/// The PDF, or a `multipart/mixed` body of the PDF followed by the captured HTML
+/// when the payload asked for it
+fn pdf_response(output: &PdfOutput) -> Response {
+    let mut response = if output.html.is_empty() {
+        (
+            [(
+                header::CONTENT_TYPE,
+                output.format.content_type().to_string(),
+            )],
+            output.pdf.clone(),
+        )
+            .into_response()
+    } else {
+        let boundary = uuid::Uuid::new_v4().simple().to_string();
+        (
+            [(
+                header::CONTENT_TYPE,
+                format!("multipart/mixed; boundary={boundary}"),
+            )],
+            multipart_body(&boundary, output),
+        )
+            .into_response()
+    };
+    for (name, value) in output.headers() {
+        if let Ok(value) = header::HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+fn multipart_body(boundary: &str, output: &PdfOutput) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut part = |content_type: &str, filename: &str, content: &[u8]| {
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Type: {content_type}\r\nContent-Disposition: attachment; filename=\"{filename}\"\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n");
+    };
+
+    part(
+        output.format.content_type(),
+        &format!("document.{}", output.format.extension()),
+        &output.pdf,
+    );
+    for (i, html) in output.html.iter().enumerate() {
+        part(
+            "text/html; charset=utf-8",
+            &format!("document-{}.html", i + 1),
+            html.as_bytes(),
+        );
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    body
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JobRequest {
+    /// Receives the PDF (or the failure) once the job finishes
+    callback_url: Option<String>,
+    /// Page loaded before the render so it finds the resources it shares with a batch of
+    /// related jobs cached
+    warm_url: Option<String>,
+    #[serde(flatten)]
+    payload: DriverPayload,
+}
+
+async fn handle_submit_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<JobRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let payload = state.registry.resolve_template(request.payload)?;
+    state.check_capacity()?;
+    let span = render_span(&headers, &payload.driver);
+    let id = span.in_scope(|| {
+        state.jobs.submit(
+            Arc::clone(&state.registry),
+            state.http.clone(),
+            payload,
+            request.callback_url,
+            request.warm_url,
+        )
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(serde_json::json!({ "id": id }))))
+}
+
+async fn handle_job_status(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.jobs.get(&id) {
+        Some(job) => job_status(&id, &job).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Cancel a job that hasn't finished yet, answering with its status afterwards
+async fn handle_cancel_job(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.jobs.cancel(&id) {
+        Some(job) => job_status(&id, &job).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn job_status(id: &str, job: &Job) -> Json<serde_json::Value> {
+    let result_url = (job.status == JobStatus::Done).then(|| format!("/jobs/{}/result", id));
+    Json(serde_json::json!({
+        "status": job.status,
+        "error": job.error,
+        "resultUrl": result_url,
+        "truncated": job.result.as_ref().map(|output| output.truncated),
+        "meta": job.result.as_ref().and_then(|output| output.meta.as_ref()),
+    }))
+}
+
+async fn handle_job_result(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let Some(job) = state.jobs.get(&id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match job.result {
+        Some(output) => pdf_response(&output),
+        None => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "status": job.status, "error": job.error })),
+        )
+            .into_response(),
+    }
+}
+
+/// Server-Sent Events for a job: where it is now, then each step until it finishes.
+///
+/// The event name is the step (`queued`, `navigating`, ..., `done` or `failed`) and the
+/// data is the event as JSON.
+async fn handle_job_events(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let Some((job, events)) = state.jobs.subscribe(&id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let stream = futures::stream::unfold(
+        Some((Some(job.current_event()), events)),
+        |state| async move {
+            let (pending, mut events) = state?;
+            let event = match pending {
+                Some(event) => event,
+                None => loop {
+                    match events.recv().await {
+                        Ok(event) => break event,
+                        // Skipped steps don't matter, the next one still says where the job is
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                },
+            };
+            let next = (!event.is_final()).then_some((None, events));
+            Some((Ok::<_, Infallible>(sse_event(&event)), next))
+        },
+    );
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn sse_event(event: &JobEvent) -> Event {
+    let name = match event {
+        JobEvent::Queued => "queued".to_string(),
+        JobEvent::Progress { step } => serde_json::to_value(step)
+            .ok()
+            .and_then(|step| step.as_str().map(str::to_string))
+            .unwrap_or_default(),
+        JobEvent::Done { .. } => "done".to_string(),
+        JobEvent::Failed { .. } => "failed".to_string(),
+        JobEvent::Cancelled => "cancelled".to_string(),
+    };
+    Event::default()
+        .event(name)
+        .json_data(event)
+        .unwrap_or_default()
+}
+
+/// Live load of every driver's pool, plus totals across drivers
+async fn handle_pool_stats(State(state): State<AppState>) -> impl IntoResponse {
+    let drivers = state.registry.stats();
+    let queued: usize = drivers.values().map(|stats| stats.queued).sum();
+    let waiting: usize = drivers.values().map(|stats| stats.waiting).sum();
+    let in_flight: usize = drivers.values().map(|stats| stats.in_flight).sum();
+
+    Json(serde_json::json!({
+        "drivers": drivers,
+        "queued": queued,
+        "waiting": waiting,
+        "inFlight": in_flight,
+        "fallbacksUsed": state.registry.fallbacks_used(),
+    }))
+}
+
+/// Liveness check for load balancers, answering as soon as the server accepts requests
+async fn handle_health() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// What the server supports, from its actual configuration
+async fn handle_capabilities(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "drivers": state.registry.capabilities(),
+        "maxBodyBytes": state.max_body_bytes,
+        "features": {
+            "otel": cfg!(feature = "otel"),
+        },
+    }))
+}
+
+/// JSON Schema of the payloads `/api/convert` and `/jobs` accept
+async fn handle_schema(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.registry.schema())
 }
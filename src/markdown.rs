@@ -0,0 +1,122 @@
+use std::sync::LazyLock;
+
+use color_eyre::eyre::{Result, eyre};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd, html};
+use syntect::highlighting::ThemeSet;
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+/// Stylesheet for rendered Markdown, with the syntect theme its code blocks use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub name: &'static str,
+    css: &'static str,
+    code_theme: &'static str,
+}
+
+/// Built-in themes, looked up by `name`; the first one is the default
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "default",
+        css: r#"
+            body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; font-size: 11pt; line-height: 1.5; color: #24292f; }
+            h1, h2 { border-bottom: 1px solid #d0d7de; padding-bottom: 0.3em; }
+            code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 0.9em; }
+            :not(pre) > code { background: #eff1f3; padding: 0.1em 0.3em; border-radius: 4px; }
+            pre { padding: 0.8em; border-radius: 6px; white-space: pre-wrap; break-inside: avoid; }
+            table { border-collapse: collapse; }
+            th, td { border: 1px solid #d0d7de; padding: 0.3em 0.8em; }
+            blockquote { margin-left: 0; padding-left: 1em; border-left: 4px solid #d0d7de; color: #57606a; }
+            img { max-width: 100%; }
+        "#,
+        code_theme: "InspiredGitHub",
+    },
+    Theme {
+        name: "serif",
+        css: r#"
+            body { font-family: Georgia, "Times New Roman", serif; font-size: 12pt; line-height: 1.6; color: #111; }
+            h1, h2, h3 { font-weight: normal; }
+            code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 0.85em; }
+            pre { padding: 0.8em; white-space: pre-wrap; break-inside: avoid; }
+            table { border-collapse: collapse; margin: 0 auto; }
+            th, td { border-top: 1px solid #999; border-bottom: 1px solid #999; padding: 0.3em 0.8em; }
+            blockquote { font-style: italic; margin: 0 2em; }
+            img { max-width: 100%; }
+        "#,
+        code_theme: "Solarized (light)",
+    },
+    Theme {
+        name: "plain",
+        css: r#"
+            pre { white-space: pre-wrap; }
+            table { border-collapse: collapse; }
+            th, td { border: 1px solid; padding: 0.2em 0.5em; }
+        "#,
+        code_theme: "InspiredGitHub",
+    },
+];
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static CODE_THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Look up a built-in theme by name, ignoring case
+pub fn find_theme(name: &str) -> Option<&'static Theme> {
+    THEMES
+        .iter()
+        .find(|theme| theme.name.eq_ignore_ascii_case(name))
+}
+
+/// Convert GitHub-flavored Markdown into a standalone HTML document styled with `theme`.
+///
+/// Fenced code blocks are highlighted by their language, with inline styles, so the
+/// document needs nothing from the network.
+pub fn to_html(markdown: &str, theme: &Theme) -> Result<String> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut events = Vec::new();
+    let mut code_block: Option<(String, String)> = None;
+    for event in Parser::new_ext(markdown, options) {
+        match (event, &mut code_block) {
+            (Event::Start(Tag::CodeBlock(kind)), None) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((language, String::new()));
+            }
+            (Event::Text(text), Some((_, code))) => code.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some(_)) => {
+                let (language, code) = code_block.take().unwrap_or_default();
+                events.push(Event::Html(highlight(&code, &language, theme)?.into()));
+            }
+            (event, _) => events.push(event),
+        }
+    }
+
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        theme.css, body
+    ))
+}
+
+/// Highlight `code` as `language`, falling back to plain text for unknown languages
+fn highlight(code: &str, language: &str, theme: &Theme) -> Result<String> {
+    let syntax = SYNTAXES
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let code_theme = CODE_THEMES
+        .themes
+        .get(theme.code_theme)
+        .ok_or_else(|| eyre!("Missing code theme `{}`", theme.code_theme))?;
+
+    highlighted_html_for_string(code, &SYNTAXES, syntax, code_theme)
+        .map_err(|e| eyre!("Failed to highlight code: {}", e))
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use chromiumoxide::{
+    Page,
+    cdp::browser_protocol::network::{
+        EventLoadingFailed, EventRequestWillBeSent, EventResponseReceived,
+    },
+    cdp::browser_protocol::performance::{DisableParams, EnableParams},
+    cdp::js_protocol::runtime::{
+        ConsoleApiCalledType, EventConsoleApiCalled, EventExceptionThrown, RemoteObject,
+    },
+};
+use color_eyre::eyre::{Context, Result};
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+
+/// Chrome's resource usage for a page, read from `Performance.getMetrics`
+#[derive(Debug, Clone, Default)]
+pub struct ResourceUsage {
+    pub js_heap_used_bytes: f64,
+    /// Heap reserved by V8; approximates the peak since it only shrinks on GC
+    pub js_heap_total_bytes: f64,
+    pub layout_count: f64,
+    pub style_recalc_count: f64,
+    pub dom_nodes: f64,
+}
+
+impl ResourceUsage {
+    /// Start collecting metrics for `page`; this adds overhead, so only do it when asked
+    pub async fn enable(page: &Page) -> Result<()> {
+        page.execute(EnableParams::default())
+            .await
+            .wrap_err("Failed to enable performance metrics")?;
+        Ok(())
+    }
+
+    /// Read the current metrics and stop collecting
+    pub async fn capture(page: &Page) -> Result<Self> {
+        let metrics = page
+            .metrics()
+            .await
+            .wrap_err("Failed to read performance metrics")?;
+        page.execute(DisableParams::default()).await?;
+
+        let mut usage = Self::default();
+        for metric in metrics {
+            let field = match metric.name.as_str() {
+                "JSHeapUsedSize" => &mut usage.js_heap_used_bytes,
+                "JSHeapTotalSize" => &mut usage.js_heap_total_bytes,
+                "LayoutCount" => &mut usage.layout_count,
+                "RecalcStyleCount" => &mut usage.style_recalc_count,
+                "Nodes" => &mut usage.dom_nodes,
+                _ => continue,
+            };
+            *field = metric.value;
+        }
+
+        Ok(usage)
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "JS heap {:.1}/{:.1} MB, {} layouts, {} style recalcs, {} DOM nodes",
+            self.js_heap_used_bytes / MB,
+            self.js_heap_total_bytes / MB,
+            self.layout_count,
+            self.style_recalc_count,
+            self.dom_nodes
+        )
+    }
+}
+
+/// Collects `console.error` calls and uncaught exceptions while it is alive
+pub struct ConsoleErrors {
+    errors: Arc<Mutex<Vec<String>>>,
+    listeners: [JoinHandle<()>; 2],
+}
+
+impl ConsoleErrors {
+    /// Start listening on `page`; errors logged before this are not seen
+    pub async fn watch(page: &Page) -> Result<Self> {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let mut console = page.event_listener::<EventConsoleApiCalled>().await?;
+        let mut exceptions = page.event_listener::<EventExceptionThrown>().await?;
+
+        let console_errors = Arc::clone(&errors);
+        let console_listener = tokio::spawn(async move {
+            while let Some(event) = console.next().await {
+                if event.r#type == ConsoleApiCalledType::Error {
+                    let message = event
+                        .args
+                        .iter()
+                        .map(describe)
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    console_errors.lock().unwrap().push(message);
+                }
+            }
+        });
+
+        let exception_errors = Arc::clone(&errors);
+        let exception_listener = tokio::spawn(async move {
+            while let Some(event) = exceptions.next().await {
+                let details = &event.exception_details;
+                let message = details
+                    .exception
+                    .as_ref()
+                    .and_then(|exception| exception.description.clone())
+                    .unwrap_or_else(|| details.text.clone());
+                exception_errors.lock().unwrap().push(message);
+            }
+        });
+
+        Ok(Self {
+            errors,
+            listeners: [console_listener, exception_listener],
+        })
+    }
+
+    /// Errors seen so far, oldest first
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+impl Drop for ConsoleErrors {
+    fn drop(&mut self) {
+        for listener in &self.listeners {
+            listener.abort();
+        }
+    }
+}
+
+/// Collects subresources that failed to load or got an error status while it is alive.
+///
+/// Requests blocked on purpose, e.g. by `loadImages: false`, and cancelled ones don't count.
+pub struct ResourceErrors {
+    errors: Arc<Mutex<Vec<String>>>,
+    listeners: [JoinHandle<()>; 3],
+}
+
+impl ResourceErrors {
+    /// Start listening on `page`, only for URLs matching one of `patterns` (`*` matches
+    /// anything) if there are any
+    pub async fn watch(page: &Page, patterns: Vec<String>) -> Result<Self> {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let urls = Arc::new(Mutex::new(HashMap::new()));
+        let mut requests = page.event_listener::<EventRequestWillBeSent>().await?;
+        let mut responses = page.event_listener::<EventResponseReceived>().await?;
+        let mut failures = page.event_listener::<EventLoadingFailed>().await?;
+
+        let request_urls = Arc::clone(&urls);
+        let request_listener = tokio::spawn(async move {
+            while let Some(event) = requests.next().await {
+                if patterns.is_empty()
+                    || patterns.iter().any(|p| matches_glob(p, &event.request.url))
+                {
+                    request_urls
+                        .lock()
+                        .unwrap()
+                        .insert(event.request_id.clone(), event.request.url.clone());
+                }
+            }
+        });
+
+        let response_errors = Arc::clone(&errors);
+        let response_urls = Arc::clone(&urls);
+        let response_listener = tokio::spawn(async move {
+            while let Some(event) = responses.next().await {
+                let status = event.response.status;
+                if status >= 400
+                    && response_urls
+                        .lock()
+                        .unwrap()
+                        .contains_key(&event.request_id)
+                {
+                    response_errors
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} ({})", event.response.url, status));
+                }
+            }
+        });
+
+        let failure_errors = Arc::clone(&errors);
+        let failure_listener = tokio::spawn(async move {
+            while let Some(event) = failures.next().await {
+                if event.canceled == Some(true) || event.blocked_reason.is_some() {
+                    continue;
+                }
+                if let Some(url) = urls.lock().unwrap().get(&event.request_id) {
+                    failure_errors
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} ({})", url, event.error_text));
+                }
+            }
+        });
+
+        Ok(Self {
+            errors,
+            listeners: [request_listener, response_listener, failure_listener],
+        })
+    }
+
+    /// Failed resources seen so far with what went wrong, oldest first
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+impl Drop for ResourceErrors {
+    fn drop(&mut self) {
+        for listener in &self.listeners {
+            listener.abort();
+        }
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of characters
+fn matches_glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Text of a console argument, as the DevTools console would roughly show it
+fn describe(arg: &RemoteObject) -> String {
+    match (&arg.value, &arg.description) {
+        (Some(serde_json::Value::String(s)), _) => s.clone(),
+        (_, Some(description)) => description.clone(),
+        (Some(value), None) => value.to_string(),
+        (None, None) => "undefined".to_string(),
+    }
+}
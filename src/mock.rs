@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, eyre};
+
+use crate::chrome::PdfDriver;
+use crate::output::PdfOutput;
+
+/// Smallest document most PDF readers accept, returned by default
+pub const MOCK_PDF: &[u8] = b"%PDF-1.4\n1 0 obj<</Type/Catalog/Pages 2 0 R>>endobj\n2 0 obj<</Type/Pages/Kids[]/Count 0>>endobj\ntrailer<</Root 1 0 R>>\n%%EOF\n";
+
+/// Driver for tests that returns canned output without launching a browser.
+///
+/// Takes any JSON payload, so it can be registered in a `DriverRegistry` like a real driver.
+#[derive(Debug)]
+pub struct MockPdfDriver {
+    pdf: Vec<u8>,
+    delay: Duration,
+    error: Option<String>,
+    calls: AtomicUsize,
+}
+
+impl Default for MockPdfDriver {
+    fn default() -> Self {
+        Self {
+            pdf: MOCK_PDF.to_vec(),
+            delay: Duration::ZERO,
+            error: None,
+            calls: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MockPdfDriver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return `pdf` instead of `MOCK_PDF`
+    pub fn with_pdf(mut self, pdf: impl Into<Vec<u8>>) -> Self {
+        self.pdf = pdf.into();
+        self
+    }
+
+    /// Take this long for every render
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Fail every render with `message`
+    pub fn failing(mut self, message: impl Into<String>) -> Self {
+        self.error = Some(message.into());
+        self
+    }
+
+    /// Number of renders started so far
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl PdfDriver for MockPdfDriver {
+    type Payload = serde_json::Value;
+
+    async fn pdf(&self, payload: Self::Payload) -> Result<PdfOutput> {
+        let _ = payload;
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+
+        match &self.error {
+            Some(message) => Err(eyre!("{}", message)),
+            None => Ok(PdfOutput::new(self.pdf.clone())),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use color_eyre::eyre::{Context, Result};
+use lopdf::{Document, Object, ObjectId};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Page count and sizes of a PDF, so clients don't have to parse it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfMeta {
+    pub pages: usize,
+    /// Width and height of each page in points, from its media box
+    pub page_sizes: Vec<(f64, f64)>,
+}
+
+impl PdfMeta {
+    pub fn from_pdf(pdf: &[u8]) -> Result<Self> {
+        let doc = Document::load_mem(pdf).wrap_err("Failed to parse PDF for page metadata")?;
+        let page_sizes: Vec<_> = doc
+            .get_pages()
+            .into_values()
+            .map(|page_id| media_box_size(&doc, page_id).unwrap_or((0.0, 0.0)))
+            .collect();
+
+        Ok(Self {
+            pages: page_sizes.len(),
+            page_sizes,
+        })
+    }
+}
+
+/// Size of the page's media box, which it may inherit from its page tree
+fn media_box_size(doc: &Document, page_id: ObjectId) -> Option<(f64, f64)> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    loop {
+        if let Ok(media_box) = node.get(b"MediaBox").and_then(|b| doc.dereference(b)) {
+            let corners: Vec<f64> = media_box
+                .1
+                .as_array()
+                .ok()?
+                .iter()
+                .filter_map(|n| n.as_float().ok().map(f64::from))
+                .collect();
+            let [x0, y0, x1, y1] = corners[..] else {
+                return None;
+            };
+            return Some(((x1 - x0).abs(), (y1 - y0).abs()));
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+}
+
+/// What Chrome's `Page.printToPDF` answered for one document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrintInfo {
+    /// Answered with a stream handle rather than the PDF inline
+    pub stream: bool,
+    /// Size of the PDF after decoding
+    pub bytes: usize,
+}
+
+/// What a render produces: a PDF, or a screenshot of the page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Pdf,
+    Png,
+    Jpeg,
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Pdf => "application/pdf",
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+        }
+    }
+
+    pub fn is_image(self) -> bool {
+        self != Self::Pdf
+    }
+}
+
+/// Whether browsers should show the PDF or save it, when it has a filename
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    #[default]
+    Attachment,
+    Inline,
+}
+
+/// `Content-Disposition` value for `filename`, `None` when nothing of it is usable.
+///
+/// Characters that could break out of the header or name a path are replaced, and names
+/// beyond ASCII also go in an RFC 5987 `filename*`, which modern browsers prefer.
+pub fn content_disposition(filename: &str, disposition: Disposition) -> Option<String> {
+    let filename: String = filename
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | '/' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if filename.is_empty() {
+        return None;
+    }
+
+    let kind = match disposition {
+        Disposition::Attachment => "attachment",
+        Disposition::Inline => "inline",
+    };
+    let ascii: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    if ascii == filename {
+        return Some(format!("{kind}; filename=\"{filename}\""));
+    }
+
+    let encoded: String = filename
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (byte as char).to_string(),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect();
+    Some(format!(
+        "{kind}; filename=\"{ascii}\"; filename*=UTF-8''{encoded}"
+    ))
+}
+
+/// A rendered PDF along with how it was produced
+#[derive(Debug, Clone, Default)]
+pub struct PdfOutput {
+    pub pdf: Vec<u8>,
+    /// Rendering stopped waiting before the page was ready, so content may be missing
+    pub truncated: bool,
+    /// DOM of each rendered document as it was printed, in order; only captured on request
+    pub html: Vec<String>,
+    /// Page count and sizes; only computed on request
+    pub meta: Option<PdfMeta>,
+    /// Tasks ahead of this one when it was queued, for drivers with a queue
+    pub queue_position: Option<usize>,
+    /// The PDF seems to have nothing on it; `None` when that wasn't checked
+    pub blank: Option<bool>,
+    /// How each rendered document was printed, in order; only collected for debug renders
+    pub print: Vec<PrintInfo>,
+    /// `Content-Disposition` for the PDF, from the payload's `filename`
+    pub content_disposition: Option<String>,
+    /// What `pdf` holds, a screenshot for image formats
+    pub format: OutputFormat,
+}
+
+impl PdfOutput {
+    pub fn new(pdf: Vec<u8>) -> Self {
+        Self {
+            pdf,
+            truncated: false,
+            html: Vec::new(),
+            meta: None,
+            queue_position: None,
+            blank: None,
+            print: Vec::new(),
+            content_disposition: None,
+            format: OutputFormat::Pdf,
+        }
+    }
+
+    /// HTTP headers describing the output, sent along with the PDF
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(disposition) = &self.content_disposition {
+            headers.push(("Content-Disposition", disposition.clone()));
+        }
+        if self.truncated {
+            headers.push(("X-Pdfan-Truncated", "true".to_string()));
+        }
+        if self.blank == Some(true) {
+            headers.push(("X-Pdfan-Blank", "true".to_string()));
+        }
+        if let Some(position) = self.queue_position {
+            headers.push(("X-Queue-Position", position.to_string()));
+        }
+        if let Some(meta) = &self.meta {
+            headers.push(("X-Pdfan-Pages", meta.pages.to_string()));
+            let sizes: Vec<String> = meta
+                .page_sizes
+                .iter()
+                .map(|(width, height)| format!("{width}x{height}"))
+                .collect();
+            headers.push(("X-Pdfan-Page-Sizes", sizes.join(",")));
+        }
+        if !self.print.is_empty() {
+            // e.g. `inline:52311,inline:48210`
+            let prints: Vec<String> = self
+                .print
+                .iter()
+                .map(|print| {
+                    let transfer = if print.stream { "stream" } else { "inline" };
+                    format!("{transfer}:{}", print.bytes)
+                })
+                .collect();
+            headers.push(("X-Pdfan-Print", prints.join(",")));
+        }
+        headers
+    }
+}
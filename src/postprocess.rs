@@ -0,0 +1,799 @@
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{Context, Result, eyre};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, xref::XrefType};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// PDF versions accepted for `pdf_version`
+pub const PDF_VERSIONS: &[&str] = &["1.3", "1.4", "1.5", "1.6", "1.7", "2.0"];
+
+/// Post-processing steps applied to a rendered PDF
+#[derive(Debug, Clone, Default)]
+pub struct PostProcess {
+    /// Rewrite the document to declare this PDF version
+    pub pdf_version: Option<String>,
+    /// Files to embed in the document, listed by viewers as attachments
+    pub attachments: Vec<Attachment>,
+    /// Entries to set in the document's Info dictionary
+    pub info: DocumentInfo,
+    /// Bake annotations into the pages and drop the form, see `flatten`
+    pub flatten: bool,
+    /// Clear the header and footer off every page outside these, see `strip_header_footer`
+    pub header_footer_pages: Option<HeaderFooterPages>,
+    pub compression: CompressionLevel,
+}
+
+/// How hard to compress the PDF after rendering, trading CPU for size.
+///
+/// On Typst's PDFs, which have no object streams, `default` took 14% off a 20 KB invoice
+/// and 9% off a 53 KB text report, `max` 14% and 11%. A PDF already packed as tightly comes
+/// out no smaller, and is returned as it was when compression is the only step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionLevel {
+    /// Keep Chrome's output as it is
+    #[default]
+    None,
+    /// Lossless cleanup as in `compress`: drop unused objects, deflate uncompressed
+    /// streams and pack objects into object streams
+    Default,
+    /// `default`, and also inflate Chrome's deflated streams to deflate them again at
+    /// the highest level. Slowest, and only gains where Chrome deflated below that level.
+    Max,
+}
+
+/// Pages that keep the header and footer Chrome printed, and where those sit
+#[derive(Debug, Clone)]
+pub struct HeaderFooterPages {
+    /// Page ranges such as `2-` or `1, 3-5`, counted from 1
+    pub ranges: String,
+    /// Top and bottom margins in inches, where Chrome draws the header and footer
+    pub top: f64,
+    pub bottom: f64,
+}
+
+/// Document Info dictionary entries; unset fields keep what the renderer wrote
+#[derive(Debug, Clone, Default)]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    /// PDF date, e.g. `D:20240131120000Z`
+    pub creation_date: Option<String>,
+}
+
+impl DocumentInfo {
+    pub fn is_empty(&self) -> bool {
+        self.entries().all(|(_, value)| value.is_none())
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&'static str, Option<&String>)> {
+        [
+            ("Title", self.title.as_ref()),
+            ("Author", self.author.as_ref()),
+            ("Subject", self.subject.as_ref()),
+            ("Keywords", self.keywords.as_ref()),
+            ("Creator", self.creator.as_ref()),
+            ("CreationDate", self.creation_date.as_ref()),
+        ]
+        .into_iter()
+    }
+}
+
+/// A file embedded in a PDF
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub mime_type: String,
+    pub description: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl PostProcess {
+    pub fn is_empty(&self) -> bool {
+        !self.edits_document() && self.compression == CompressionLevel::None
+    }
+
+    /// Whether any step but compression changes the document
+    fn edits_document(&self) -> bool {
+        self.pdf_version.is_some()
+            || !self.attachments.is_empty()
+            || !self.info.is_empty()
+            || self.flatten
+            || self.header_footer_pages.is_some()
+    }
+
+    /// Apply all requested steps, leaving the bytes untouched when there is nothing to do
+    pub fn apply(&self, pdf: Vec<u8>) -> Result<Vec<u8>> {
+        if self.is_empty() {
+            return Ok(pdf);
+        }
+
+        let mut doc = Document::load_mem(&pdf).wrap_err("Failed to parse rendered PDF")?;
+
+        if let Some(pages) = &self.header_footer_pages {
+            strip_header_footer(&mut doc, pages)?;
+        }
+        // Before attachments, so those added below are not flattened away
+        if self.flatten {
+            flatten(&mut doc)?;
+        }
+        if !self.attachments.is_empty() {
+            embed_files(&mut doc, &self.attachments)?;
+        }
+        if !self.info.is_empty() {
+            set_info(&mut doc, &self.info)?;
+        }
+        if let Some(version) = &self.pdf_version {
+            set_version(&mut doc, version)?;
+        }
+
+        if self.compression == CompressionLevel::None {
+            let mut out = Vec::with_capacity(pdf.len());
+            doc.save_to(&mut out)
+                .wrap_err("Failed to write post-processed PDF")?;
+            return Ok(out);
+        }
+        if self.compression == CompressionLevel::Max {
+            redeflate_streams(&mut doc);
+        }
+        let compressed = save_compressed(doc, pdf.len())?;
+        // Rewriting an already compact PDF, say one with object streams, can grow it a little
+        if !self.edits_document() && compressed.len() >= pdf.len() {
+            return Ok(pdf);
+        }
+        Ok(compressed)
+    }
+}
+
+/// Shrink `pdf` without touching its content: drop unused objects and compress
+/// uncompressed streams, and pack objects into object streams from PDF 1.5 on
+pub fn compress(pdf: &[u8]) -> Result<Vec<u8>> {
+    let doc = Document::load_mem(pdf).wrap_err("Failed to parse PDF for compression")?;
+    save_compressed(doc, pdf.len())
+}
+
+fn save_compressed(mut doc: Document, size_hint: usize) -> Result<Vec<u8>> {
+    doc.prune_objects();
+    doc.delete_zero_length_streams();
+    doc.compress();
+
+    let mut out = Vec::with_capacity(size_hint);
+    if doc.version.as_str() >= "1.5" {
+        doc.save_modern(&mut out)
+    } else {
+        doc.save_to(&mut out).map(|_| ())
+    }
+    .wrap_err("Failed to write compressed PDF")?;
+    Ok(out)
+}
+
+/// Deflate every stream that is only deflated again at the highest level, keeping the
+/// original where that isn't smaller. Streams with a predictor, like most images, are left
+/// alone, since recompressing them would mean re-encoding their rows too.
+fn redeflate_streams(doc: &mut Document) {
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        let only_deflated = matches!(
+            stream.dict.get(b"Filter"),
+            Ok(Object::Name(name)) if name == b"FlateDecode"
+        ) && stream.dict.get(b"DecodeParms").is_err();
+        if !only_deflated || !stream.allows_compression {
+            continue;
+        }
+
+        let mut redeflated = stream.clone();
+        if redeflated.decompress().is_err() || redeflated.compress().is_err() {
+            continue;
+        }
+        if redeflated.is_compressed() && redeflated.content.len() < stream.content.len() {
+            *stream = redeflated;
+        }
+    }
+}
+
+/// External command that reads a PDF on stdin and writes a smaller one to stdout, e.g.
+/// `gs -q -sDEVICE=pdfwrite -dPDFSETTINGS=/ebook -o - -`.
+///
+/// Unlike `compress`, it may downsample images, so output can look worse.
+#[derive(Debug, Clone)]
+pub struct Optimizer {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Optimizer {
+    /// Split a whitespace-separated command line; `None` when it is empty
+    pub fn parse(command: &str) -> Option<Self> {
+        let mut parts = command.split_whitespace().map(str::to_string);
+        Some(Self {
+            program: parts.next()?,
+            args: parts.collect(),
+        })
+    }
+
+    pub async fn run(&self, pdf: &[u8]) -> Result<Vec<u8>> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut child = tokio::process::Command::new(&self.program)
+            .args(&self.args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .wrap_err_with(|| format!("Failed to start PDF optimizer `{}`", self.program))?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = pdf.to_vec();
+        // Feed stdin concurrently, the optimizer may start writing before it read everything
+        let writer = tokio::spawn(async move {
+            stdin.write_all(&input).await?;
+            stdin.shutdown().await
+        });
+
+        let output = child
+            .wait_with_output()
+            .await
+            .wrap_err("PDF optimizer failed")?;
+        writer
+            .await
+            .wrap_err("PDF optimizer input panicked")?
+            .wrap_err("Failed to pass the PDF to the optimizer")?;
+
+        if !output.status.success() {
+            return Err(eyre!(
+                "PDF optimizer exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        if !output.stdout.starts_with(b"%PDF-") {
+            return Err(eyre!("PDF optimizer did not write a PDF"));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// Whether no page of `pdf` seems to draw anything: no text, no images and no fills in
+/// a color other than white.
+///
+/// A heuristic on the content streams, not a rendering: text in white or hidden behind
+/// other content still counts as something drawn.
+pub fn looks_blank(pdf: &[u8]) -> Result<bool> {
+    let doc = Document::load_mem(pdf).wrap_err("Failed to parse PDF for blank detection")?;
+
+    for page_id in doc.get_pages().into_values() {
+        let content = doc
+            .get_and_decode_page_content(page_id)
+            .wrap_err("Failed to decode page content")?;
+
+        let mut fill_is_white = false;
+        for operation in &content.operations {
+            let operands: Vec<f32> = operation
+                .operands
+                .iter()
+                .filter_map(|o| o.as_float().ok())
+                .collect();
+            match operation.operator.as_str() {
+                "Tj" | "TJ" | "'" | "\"" | "Do" | "sh" | "BI" => return Ok(false),
+                "g" | "rg" => fill_is_white = operands.iter().all(|c| *c >= 1.0),
+                "k" => fill_is_white = operands.iter().all(|c| *c <= 0.0),
+                "sc" | "scn" | "cs" => fill_is_white = false,
+                "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" if !fill_is_white => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Concatenate the pages of `pdfs` into one document, in order.
+///
+/// Each document's objects are renumbered into a shared id space, then their page trees
+/// are replaced by a single flat one. Document-level data such as outlines is dropped.
+pub fn merge(pdfs: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut next_id = 1;
+    let mut pages = Vec::new();
+    let mut objects = BTreeMap::new();
+
+    for (i, pdf) in pdfs.iter().enumerate() {
+        let mut doc = Document::load_mem(pdf)
+            .wrap_err_with(|| format!("Failed to parse document {} for merging", i + 1))?;
+        doc.renumber_objects_with(next_id);
+        next_id = doc.max_id + 1;
+
+        let doc_pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        for &page_id in &doc_pages {
+            inherit_page_attributes(&mut doc, page_id);
+        }
+        pages.extend(doc_pages);
+        objects.extend(doc.objects);
+    }
+
+    let mut merged = Document::with_version("1.4");
+    merged.max_id = next_id;
+    let pages_id = merged.new_object_id();
+
+    for (id, object) in objects {
+        match object.type_name() {
+            Ok(b"Catalog") | Ok(b"Pages") | Ok(b"Outlines") | Ok(b"Outline") => {}
+            Ok(b"Page") => {
+                let mut object = object;
+                if let Object::Dictionary(page) = &mut object {
+                    page.set("Parent", pages_id);
+                }
+                merged.objects.insert(id, object);
+            }
+            _ => {
+                merged.objects.insert(id, object);
+            }
+        }
+    }
+
+    merged.objects.insert(
+        pages_id,
+        Object::Dictionary(Dictionary::from_iter([
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Count", Object::Integer(pages.len() as i64)),
+            (
+                "Kids",
+                Object::Array(pages.into_iter().map(Object::Reference).collect()),
+            ),
+        ])),
+    );
+    let catalog_id = merged.add_object(Dictionary::from_iter([
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    merged.trailer.set("Root", catalog_id);
+
+    let mut out = Vec::new();
+    merged
+        .save_to(&mut out)
+        .wrap_err("Failed to write merged PDF")?;
+    Ok(out)
+}
+
+/// Copy attributes a page inherits from its page tree onto the page itself,
+/// so it still has them once that tree is gone
+fn inherit_page_attributes(doc: &mut Document, page_id: ObjectId) {
+    const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+    let Ok(page) = doc.get_dictionary(page_id) else {
+        return;
+    };
+    let mut inherited: Vec<(&[u8], Object)> = Vec::new();
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+
+    while let Some(node) = parent.and_then(|id| doc.get_dictionary(id).ok()) {
+        for key in INHERITABLE {
+            if !page.has(key)
+                && !inherited.iter().any(|(k, _)| *k == key)
+                && let Ok(value) = node.get(key)
+            {
+                inherited.push((key, value.clone()));
+            }
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        for (key, value) in inherited {
+            page.set(key, value);
+        }
+    }
+}
+
+/// Whether `date` is a PDF date: `D:YYYYMMDDHHmmSS`, optionally followed by a UTC
+/// offset such as `Z` or `+01'00'`
+pub fn is_pdf_date(date: &str) -> bool {
+    let Some(rest) = date.strip_prefix("D:") else {
+        return false;
+    };
+    let Some(digits) = rest
+        .get(..14)
+        .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))
+    else {
+        return false;
+    };
+    let field = |range: std::ops::Range<usize>| digits[range].parse::<u32>().unwrap_or(0);
+    let in_range = (1..=12).contains(&field(4..6))
+        && (1..=31).contains(&field(6..8))
+        && field(8..10) <= 23
+        && field(10..12) <= 59
+        && field(12..14) <= 59;
+
+    let offset = &rest[14..];
+    let valid_offset = match offset.as_bytes() {
+        [] | [b'Z'] => true,
+        [b'+' | b'-', h1, h2, b'\'', m1, m2, b'\''] => {
+            [h1, h2, m1, m2].iter().all(|b| b.is_ascii_digit())
+                && offset[1..3].parse::<u32>().is_ok_and(|h| h <= 23)
+                && offset[4..6].parse::<u32>().is_ok_and(|m| m <= 59)
+        }
+        _ => false,
+    };
+
+    in_range && valid_offset
+}
+
+/// Bake annotations into the page content and drop the form, leaving nothing interactive.
+///
+/// Visible annotations with a normal appearance are drawn where they sit, their BBox
+/// stretched onto their Rect; an appearance's own Matrix is not applied. Annotations without
+/// an appearance, such as links, are removed rather than drawn, and so is the AcroForm with
+/// its field values and scripts.
+fn flatten(doc: &mut Document) -> Result<()> {
+    for page_id in doc.get_pages().into_values() {
+        let annots = match doc.get_dictionary(page_id)?.get(b"Annots") {
+            Ok(Object::Reference(id)) => doc.get_object(*id)?.as_array()?.clone(),
+            Ok(Object::Array(annots)) => annots.clone(),
+            _ => continue,
+        };
+
+        // Resources set on the page hide the inherited ones, which its content still uses
+        inherit_page_attributes(doc, page_id);
+        let mut content = String::new();
+        for (i, annot) in annots.iter().enumerate() {
+            let Some((appearance_id, transform)) = annotation_appearance(doc, annot) else {
+                continue;
+            };
+            // Unique across pages, which may share their resources dictionary
+            let name = format!("PdfanFlat{}_{}", page_id.0, i);
+            doc.add_xobject(page_id, name.as_str(), appearance_id)?;
+            let [a, b, c, d, e, f] = transform;
+            content.push_str(&format!("q {a} {b} {c} {d} {e} {f} cm /{name} Do Q\n"));
+        }
+
+        if !content.is_empty() {
+            doc.add_page_contents(page_id, content.into_bytes())?;
+        }
+        doc.get_dictionary_mut(page_id)?.remove(b"Annots");
+    }
+
+    let catalog = doc.catalog_mut().wrap_err("PDF has no catalog")?;
+    catalog.remove(b"AcroForm");
+    Ok(())
+}
+
+/// Clip the pages outside `pages.ranges` to the area between their margins.
+///
+/// Chrome draws the header and footer into the page's own content, so they can't be told
+/// apart from it; whatever else reaches into the margins is cut off too, like a full-page
+/// background. The margins are the ones requested, so a header that `templateFit: grow`
+/// made taller is only cleared up to the original margin.
+fn strip_header_footer(doc: &mut Document, pages: &HeaderFooterPages) -> Result<()> {
+    for (number, page_id) in doc.get_pages() {
+        if in_page_ranges(&pages.ranges, number) {
+            continue;
+        }
+        let Some([x0, y0, x1, y1]) = media_box(doc, page_id) else {
+            continue;
+        };
+
+        let bottom = y0 + (pages.bottom * 72.0) as f32;
+        let height = (y1 - y0) - ((pages.top + pages.bottom) * 72.0) as f32;
+        let clip = format!("q {x0} {bottom} {} {} re W n\n", x1 - x0, height.max(0.0));
+        wrap_page_contents(doc, page_id, clip.into_bytes(), b"\nQ\n".to_vec())?;
+    }
+    Ok(())
+}
+
+/// Whether page `number` falls in `ranges`, as validated by the payload
+fn in_page_ranges(ranges: &str, number: u32) -> bool {
+    ranges.split(',').any(|range| {
+        let range = range.trim();
+        match range.split_once('-') {
+            Some((from, to)) => {
+                let from = from.trim().parse().unwrap_or(1);
+                let to = to.trim().parse().unwrap_or(u32::MAX);
+                (from..=to).contains(&number)
+            }
+            None => range.parse() == Ok(number),
+        }
+    })
+}
+
+/// The page's media box, which it may inherit from its page tree
+fn media_box(doc: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    loop {
+        if let Ok(media_box) = node.get(b"MediaBox") {
+            return rectangle(doc.dereference(media_box).ok()?.1);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+}
+
+/// Put `before` ahead of the page's content streams and `after` behind them
+fn wrap_page_contents(
+    doc: &mut Document,
+    page_id: ObjectId,
+    before: Vec<u8>,
+    after: Vec<u8>,
+) -> Result<()> {
+    let mut contents = match doc.get_dictionary(page_id)?.get(b"Contents") {
+        Ok(contents) => match doc.dereference(contents)? {
+            (_, Object::Array(streams)) => streams.clone(),
+            (Some(id), _) => vec![Object::Reference(id)],
+            (None, _) => return Err(eyre!("Page contents are not a stream")),
+        },
+        Err(_) => Vec::new(),
+    };
+
+    let before = doc.add_object(Stream::new(Dictionary::new(), before));
+    let after = doc.add_object(Stream::new(Dictionary::new(), after));
+    contents.insert(0, Object::Reference(before));
+    contents.push(Object::Reference(after));
+    doc.get_dictionary_mut(page_id)?
+        .set("Contents", Object::Array(contents));
+    Ok(())
+}
+
+/// Normal appearance stream of a visible annotation, with the matrix placing it on the page
+fn annotation_appearance(doc: &Document, annot: &Object) -> Option<(ObjectId, [f32; 6])> {
+    const HIDDEN: i64 = 1 << 1;
+
+    let annot = doc.dereference(annot).ok()?.1.as_dict().ok()?;
+    let flags = annot.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+    if flags & HIDDEN != 0 {
+        return None;
+    }
+
+    let appearances = doc
+        .dereference(annot.get(b"AP").ok()?)
+        .ok()?
+        .1
+        .as_dict()
+        .ok()?;
+    let normal = appearances.get(b"N").ok()?;
+    // Checkboxes and radio buttons have one appearance per state, picked by AS
+    let appearance_id = match doc.dereference(normal).ok()? {
+        (Some(id), Object::Stream(_)) => id,
+        (_, Object::Dictionary(states)) => {
+            let state = annot.get(b"AS").and_then(Object::as_name).ok()?;
+            states.get(state).and_then(Object::as_reference).ok()?
+        }
+        _ => return None,
+    };
+
+    let rect = rectangle(annot.get(b"Rect").ok()?)?;
+    let stream = doc.get_object(appearance_id).ok()?.as_stream().ok()?;
+    let bbox = stream
+        .dict
+        .get(b"BBox")
+        .ok()
+        .and_then(rectangle)
+        .unwrap_or([0.0, 0.0, rect[2] - rect[0], rect[3] - rect[1]]);
+
+    let (width, height) = (bbox[2] - bbox[0], bbox[3] - bbox[1]);
+    if width == 0.0 || height == 0.0 {
+        return None;
+    }
+    let sx = (rect[2] - rect[0]) / width;
+    let sy = (rect[3] - rect[1]) / height;
+    Some((
+        appearance_id,
+        [
+            sx,
+            0.0,
+            0.0,
+            sy,
+            rect[0] - bbox[0] * sx,
+            rect[1] - bbox[1] * sy,
+        ],
+    ))
+}
+
+/// `[llx lly urx ury]` of a PDF rectangle, normalized so the lower left corner comes first
+fn rectangle(object: &Object) -> Option<[f32; 4]> {
+    let values: Vec<f32> = object
+        .as_array()
+        .ok()?
+        .iter()
+        .map(|value| value.as_float().ok())
+        .collect::<Option<_>>()?;
+    let [x1, y1, x2, y2] = values.try_into().ok()?;
+    Some([x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)])
+}
+
+/// Set the entries of `info` in the document's Info dictionary, creating it if needed
+fn set_info(doc: &mut Document, info: &DocumentInfo) -> Result<()> {
+    let mut dict = match doc.trailer.get(b"Info") {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id)?.clone(),
+        Ok(Object::Dictionary(dict)) => dict.clone(),
+        _ => Dictionary::new(),
+    };
+
+    for (key, value) in info.entries() {
+        if let Some(value) = value {
+            dict.set(key, text_string(value));
+        }
+    }
+
+    let info_id = doc.add_object(dict);
+    doc.trailer.set("Info", info_id);
+    Ok(())
+}
+
+/// A PDF text string: plain for ASCII, UTF-16BE with a byte order mark otherwise
+fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let bytes = [0xFE, 0xFF]
+        .into_iter()
+        .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+        .collect();
+    Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+}
+
+/// Add `attachments` to the document's `/EmbeddedFiles` name tree.
+///
+/// The files are stored flate-compressed, so the document grows by about their
+/// compressed size. Files already embedded are kept.
+fn embed_files(doc: &mut Document, attachments: &[Attachment]) -> Result<()> {
+    let mut entries = Vec::new();
+
+    for attachment in attachments {
+        let mut file = Stream::new(
+            Dictionary::from_iter([
+                ("Type", Object::Name(b"EmbeddedFile".to_vec())),
+                (
+                    "Subtype",
+                    Object::Name(attachment.mime_type.as_bytes().to_vec()),
+                ),
+                (
+                    "Params",
+                    Object::Dictionary(Dictionary::from_iter([(
+                        "Size",
+                        Object::Integer(attachment.data.len() as i64),
+                    )])),
+                ),
+            ]),
+            attachment.data.clone(),
+        );
+        file.compress()
+            .wrap_err_with(|| format!("Failed to compress attachment `{}`", attachment.name))?;
+        let file_id = doc.add_object(file);
+
+        let mut spec = Dictionary::from_iter([
+            ("Type", Object::Name(b"Filespec".to_vec())),
+            ("F", Object::string_literal(attachment.name.as_str())),
+            ("UF", text_string(&attachment.name)),
+            (
+                "EF",
+                Object::Dictionary(Dictionary::from_iter([("F", Object::Reference(file_id))])),
+            ),
+        ]);
+        if let Some(description) = &attachment.description {
+            spec.set("Desc", text_string(description));
+        }
+        let spec_id = doc.add_object(spec);
+
+        entries.push((attachment.name.clone(), spec_id));
+    }
+
+    let catalog = doc.catalog().wrap_err("Rendered PDF has no catalog")?;
+    let mut names = match catalog.get(b"Names") {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id)?.clone(),
+        Ok(Object::Dictionary(names)) => names.clone(),
+        _ => Dictionary::new(),
+    };
+    let mut tree = match names.get(b"EmbeddedFiles") {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id)?.clone(),
+        Ok(Object::Dictionary(tree)) => tree.clone(),
+        _ => Dictionary::new(),
+    };
+
+    // A leaf's keys must be sorted, so rebuild the whole array
+    let mut existing: Vec<Object> = tree
+        .get(b"Names")
+        .and_then(Object::as_array)
+        .cloned()
+        .unwrap_or_default();
+    for (name, spec_id) in entries {
+        existing.push(Object::string_literal(name));
+        existing.push(Object::Reference(spec_id));
+    }
+    let mut pairs: Vec<(Vec<u8>, Object)> = existing
+        .chunks_exact(2)
+        .filter_map(|pair| Some((pair[0].as_str().ok()?.to_vec(), pair[1].clone())))
+        .collect();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    tree.set(
+        "Names",
+        Object::Array(
+            pairs
+                .into_iter()
+                .flat_map(|(name, spec)| [Object::string_literal(name), spec])
+                .collect(),
+        ),
+    );
+
+    names.set("EmbeddedFiles", doc.add_object(tree));
+    let names_id = doc.add_object(names);
+    doc.catalog_mut()?.set("Names", names_id);
+
+    Ok(())
+}
+
+/// Make the document declare `version`, downgrading its structure where needed.
+///
+/// Chrome emits PDF 1.4 headers but may rely on newer features. The conversion is lossy
+/// in structure only:
+/// - below 1.5, object streams are expanded into plain objects and the cross-reference
+///   stream is replaced by a classic xref table, which makes the file larger
+/// - below 1.4, transparency can't be removed without changing how pages look, so the
+///   conversion fails when the document uses it
+fn set_version(doc: &mut Document, version: &str) -> Result<()> {
+    if !PDF_VERSIONS.contains(&version) {
+        return Err(eyre!("Unsupported PDF version `{}`", version));
+    }
+
+    if version < "1.4" && uses_transparency(doc) {
+        return Err(eyre!(
+            "Cannot downgrade to PDF {}: the document uses transparency",
+            version
+        ));
+    }
+
+    if version < "1.5" {
+        // Objects from object streams are already loaded individually, so writing a
+        // classic xref table is all it takes to drop both 1.5 features
+        doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+    }
+
+    // The catalog's /Version overrides the header and would contradict it
+    if let Ok(catalog) = doc.catalog_mut() {
+        catalog.remove(b"Version");
+    }
+    doc.version = version.to_string();
+
+    Ok(())
+}
+
+/// Whether any object uses transparency groups, soft masks, blend modes or constant alpha
+fn uses_transparency(doc: &Document) -> bool {
+    doc.objects.values().any(|object| match object {
+        Object::Dictionary(dict) => dict_uses_transparency(dict),
+        Object::Stream(stream) => dict_uses_transparency(&stream.dict),
+        _ => false,
+    })
+}
+
+fn dict_uses_transparency(dict: &Dictionary) -> bool {
+    let is_transparency_group = dict
+        .get(b"S")
+        .and_then(Object::as_name)
+        .is_ok_and(|s| s == b"Transparency");
+    let has_soft_mask = dict
+        .get(b"SMask")
+        .is_ok_and(|mask| mask.as_name().map_or(true, |name| name != b"None"));
+    let has_blend_mode = dict
+        .get(b"BM")
+        .and_then(Object::as_name)
+        .is_ok_and(|mode| mode != b"Normal" && mode != b"Compatible");
+    let has_alpha = [b"CA".as_slice(), b"ca".as_slice()].iter().any(|key| {
+        dict.get(key)
+            .and_then(Object::as_float)
+            .is_ok_and(|a| a < 1.0)
+    });
+
+    is_transparency_group
+        || has_soft_mask
+        || has_blend_mode
+        || has_alpha
+        || dict.iter().any(|(_, value)| match value {
+            Object::Dictionary(nested) => dict_uses_transparency(nested),
+            _ => false,
+        })
+}
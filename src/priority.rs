@@ -0,0 +1,69 @@
+use color_eyre::eyre::{Result, eyre};
+
+/// Set the nice value of process `pid` and every process it started.
+///
+/// Linux schedules threads, not processes, so each thread is reniced on its own; processes
+/// forked afterwards inherit the value of the thread that forks them. Elsewhere on Unix,
+/// only `pid` itself is reniced.
+pub fn renice_tree(pid: u32, nice: i32) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut pids = vec![pid];
+        while let Some(pid) = pids.pop() {
+            for tid in threads(pid) {
+                renice(tid, nice)?;
+            }
+            pids.extend(children(pid));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    renice(pid, nice)
+}
+
+fn renice(id: u32, nice: i32) -> Result<()> {
+    // SAFETY: setpriority only reads its arguments
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, id as libc::id_t, nice) };
+    if result == -1 {
+        let error = std::io::Error::last_os_error();
+        // The thread or process exited since it was listed
+        if error.raw_os_error() != Some(libc::ESRCH) {
+            return Err(eyre!(
+                "Failed to set the nice value of {} to {}: {}",
+                id,
+                nice,
+                error
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Thread ids of process `pid`, empty once it has exited
+#[cfg(target_os = "linux")]
+fn threads(pid: u32) -> Vec<u32> {
+    let Ok(tasks) = std::fs::read_dir(format!("/proc/{}/task", pid)) else {
+        return Vec::new();
+    };
+    tasks
+        .filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok())
+        .collect()
+}
+
+/// Ids of the processes whose parent is `pid`
+#[cfg(target_os = "linux")]
+fn children(pid: u32) -> Vec<u32> {
+    threads(pid)
+        .into_iter()
+        .filter_map(|tid| {
+            std::fs::read_to_string(format!("/proc/{}/task/{}/children", pid, tid)).ok()
+        })
+        .flat_map(|children| {
+            children
+                .split_whitespace()
+                .filter_map(|child| child.parse().ok())
+                .collect::<Vec<u32>>()
+        })
+        .collect()
+}
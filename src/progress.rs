@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+
+/// Lifecycle events reported while a render is in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderEvent {
+    /// A worker picked the render up
+    Started,
+    /// Loading the URL or HTML
+    Navigating,
+    /// Waiting for the page's network requests to settle
+    WaitingForIdle,
+    /// Waiting for the page to signal it is ready, by event or expression
+    WaitingForPage,
+    Printing,
+}
+
+/// Receives render events. Cheap to clone; the default ignores everything.
+#[derive(Clone, Default)]
+pub struct Progress(Option<Arc<dyn Fn(RenderEvent) + Send + Sync>>);
+
+impl Progress {
+    pub fn new(on_event: impl Fn(RenderEvent) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(on_event)))
+    }
+
+    pub fn report(&self, event: RenderEvent) {
+        if let Some(on_event) = &self.0 {
+            on_event(event);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use color_eyre::eyre::{Context, Result};
+use futures::future::BoxFuture;
+use schemars::{JsonSchema, generate::SchemaSettings};
+use serde::{Deserialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::chrome::PdfDriver;
+use crate::error::{PdfError, ValidationError};
+use crate::output::{Disposition, PdfOutput, content_disposition};
+use crate::progress::Progress;
+use crate::worker::{PoolFull, PoolStats};
+
+/// Driver used when a payload doesn't name one
+pub const DEFAULT_DRIVER: &str = "chrome";
+
+/// Object-safe view of a `PdfDriver` that takes its payload as JSON
+pub trait DynPdfDriver: Send + Sync {
+    fn pdf(
+        &self,
+        payload: serde_json::Value,
+        progress: Progress,
+    ) -> BoxFuture<'_, Result<PdfOutput>>;
+
+    fn stats(&self) -> Option<PoolStats>;
+
+    /// Whether the JSON `payload` is cacheable, false when it doesn't parse
+    fn is_cacheable(&self, payload: &serde_json::Value) -> bool;
+
+    fn capabilities(&self) -> serde_json::Value;
+
+    fn prime_cache<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// JSON Schema of the payload the driver takes, without the `driver` field
+    fn schema(&self) -> serde_json::Value;
+}
+
+impl<D> DynPdfDriver for D
+where
+    D: PdfDriver + Send + Sync,
+    D::Payload: DeserializeOwned + JsonSchema + Send,
+{
+    fn pdf(
+        &self,
+        payload: serde_json::Value,
+        progress: Progress,
+    ) -> BoxFuture<'_, Result<PdfOutput>> {
+        Box::pin(async move {
+            let payload = serde_json::from_value(payload).map_err(|e| {
+                PdfError::Validation(vec![ValidationError::new("payload", e.to_string())])
+            })?;
+            self.pdf_with_progress(payload, progress).await
+        })
+    }
+
+    fn stats(&self) -> Option<PoolStats> {
+        PdfDriver::stats(self)
+    }
+
+    fn is_cacheable(&self, payload: &serde_json::Value) -> bool {
+        D::Payload::deserialize(payload)
+            .is_ok_and(|payload| PdfDriver::is_cacheable(self, &payload))
+    }
+
+    fn capabilities(&self) -> serde_json::Value {
+        PdfDriver::capabilities(self)
+    }
+
+    fn prime_cache<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(PdfDriver::prime_cache(self, url))
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        // Inlined, so it can be embedded in another schema without dangling `$ref`s
+        SchemaSettings::draft2020_12()
+            .with(|settings| settings.inline_subschemas = true)
+            .into_generator()
+            .into_root_schema_for::<D::Payload>()
+            .to_value()
+    }
+}
+
+/// A payload tagged with the name of the driver that renders it.
+///
+/// The remaining fields are handed to the driver untouched, so
+/// `{ "driver": "chrome", "html": "..." }` reaches the Chrome driver as `{ "html": "..." }`.
+#[derive(Debug, Deserialize)]
+pub struct DriverPayload {
+    #[serde(default = "default_driver")]
+    pub driver: String,
+    /// Name to save the PDF under, sent as `Content-Disposition`; none is sent without it
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub disposition: Disposition,
+    /// Server-side template whose options fill in the fields this payload leaves out
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(flatten)]
+    pub payload: serde_json::Value,
+}
+
+impl DriverPayload {
+    /// Weak HTTP entity tag for the PDF this payload renders to with the server `settings`,
+    /// e.g. `Config::render_fingerprint`.
+    ///
+    /// A SHA-256 of the settings, the driver name and the payload JSON, whose object keys
+    /// serialize in sorted order, so equal payloads get equal tags across requests and
+    /// restarts. Renders aren't byte for byte the same, their creation date differs and
+    /// HTML may load images from elsewhere, so the tag is only ever weak.
+    pub fn etag(&self, settings: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(settings.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.driver.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.payload.to_string().as_bytes());
+        let hash: String = hasher.finalize()[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        format!("W/\"{hash}\"")
+    }
+}
+
+fn default_driver() -> String {
+    DEFAULT_DRIVER.to_string()
+}
+
+/// Drivers available to the HTTP layer, looked up by name
+#[derive(Default)]
+pub struct DriverRegistry {
+    drivers: HashMap<String, Arc<dyn DynPdfDriver>>,
+    /// Driver that retries a payload another one failed to render, by driver name
+    fallbacks: HashMap<String, String>,
+    /// Renders that only succeeded or failed on a fallback driver
+    fallbacks_used: AtomicU64,
+    /// Default payload fields, by template name
+    templates: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+impl DriverRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `driver` available under `name`, replacing any driver already registered there
+    pub fn register(&mut self, name: impl Into<String>, driver: impl DynPdfDriver + 'static) {
+        self.drivers.insert(name.into(), Arc::new(driver));
+    }
+
+    /// Render payloads `driver` fails on again with `fallback`, which must be registered too.
+    ///
+    /// Only unexplained failures fall back; a `PdfError`, like a page that doesn't load,
+    /// would fail the same way on any driver, and a full pool or a render out of time
+    /// would only double the work.
+    pub fn set_fallback(&mut self, driver: impl Into<String>, fallback: impl Into<String>) {
+        self.fallbacks.insert(driver.into(), fallback.into());
+    }
+
+    /// Make `defaults` available to payloads as `"template": name`
+    pub fn set_template(
+        &mut self,
+        name: impl Into<String>,
+        defaults: serde_json::Map<String, serde_json::Value>,
+    ) {
+        self.templates.insert(name.into(), defaults);
+    }
+
+    /// Load every `<name>.json` in `dir` as template `name`, returning how many there were
+    pub fn load_templates(&mut self, dir: &Path) -> Result<usize> {
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir)
+            .wrap_err_with(|| format!("Failed to read templates from {}", dir.display()))?
+        {
+            let path = entry?.path();
+            let Some(name) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .filter(|_| path.extension().is_some_and(|ext| ext == "json"))
+            else {
+                continue;
+            };
+            let file = std::fs::read(&path)
+                .wrap_err_with(|| format!("Failed to read template {}", path.display()))?;
+            let defaults = serde_json::from_slice(&file)
+                .wrap_err_with(|| format!("Template {} is not a JSON object", path.display()))?;
+            self.set_template(name, defaults);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Fill in the fields `payload` leaves out from the template it names, fields the
+    /// request sets win. Payloads without a template pass through unchanged.
+    pub fn resolve_template(&self, mut payload: DriverPayload) -> Result<DriverPayload> {
+        let Some(name) = payload.template.take() else {
+            return Ok(payload);
+        };
+        let defaults = self.templates.get(&name).ok_or_else(|| {
+            PdfError::Validation(vec![ValidationError::new(
+                "template",
+                format!("unknown template `{}`", name),
+            )])
+        })?;
+
+        if let serde_json::Value::Object(fields) = &mut payload.payload {
+            for (key, value) in defaults {
+                fields.entry(key).or_insert_with(|| value.clone());
+            }
+        }
+        Ok(payload)
+    }
+
+    /// How many renders have gone to a fallback driver so far
+    pub fn fallbacks_used(&self) -> u64 {
+        self.fallbacks_used.load(Ordering::Relaxed)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn DynPdfDriver>> {
+        self.drivers.get(name).cloned()
+    }
+
+    /// Pool load of every driver that has a pool, by driver name
+    pub fn stats(&self) -> HashMap<&str, PoolStats> {
+        self.drivers
+            .iter()
+            .filter_map(|(name, driver)| Some((name.as_str(), driver.stats()?)))
+            .collect()
+    }
+
+    /// What every driver supports, by driver name
+    pub fn capabilities(&self) -> HashMap<&str, serde_json::Value> {
+        self.drivers
+            .iter()
+            .map(|(name, driver)| (name.as_str(), driver.capabilities()))
+            .collect()
+    }
+
+    /// JSON Schema of a `DriverPayload` any registered driver accepts.
+    ///
+    /// One branch per driver, told apart by `driver`, which only the default driver's
+    /// payloads may leave out.
+    pub fn schema(&self) -> serde_json::Value {
+        let mut names: Vec<&str> = self.names().collect();
+        names.sort_unstable();
+
+        let branches: Vec<serde_json::Value> = names
+            .into_iter()
+            .map(|name| {
+                let mut payload = self.drivers[name].schema();
+                if let Some(payload) = payload.as_object_mut() {
+                    payload.remove("$schema");
+                }
+                let required: &[&str] = if name == DEFAULT_DRIVER {
+                    &[]
+                } else {
+                    &["driver"]
+                };
+                serde_json::json!({
+                    "allOf": [
+                        payload,
+                        {
+                            "properties": {
+                                "driver": { "const": name },
+                                "filename": { "type": "string" },
+                                "disposition": { "enum": ["attachment", "inline"] },
+                                "template": { "type": "string" },
+                            },
+                            "required": required,
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "DriverPayload",
+            "oneOf": branches,
+        })
+    }
+
+    /// Names of all registered drivers
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.drivers.keys().map(String::as_str)
+    }
+
+    /// Whether the driver `payload` names can render it from the payload alone
+    pub fn is_cacheable(&self, payload: &DriverPayload) -> bool {
+        self.get(&payload.driver)
+            .is_some_and(|driver| driver.is_cacheable(&payload.payload))
+    }
+
+    /// Warm the cache of the driver named `driver` with `url`, see `PdfDriver::prime_cache`.
+    /// An unknown driver is left for the render to report.
+    pub async fn prime_cache(&self, driver: &str, url: &str) -> Result<()> {
+        match self.get(driver) {
+            Some(driver) => driver.prime_cache(url).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Render `payload` with the driver it names
+    pub async fn pdf(&self, payload: DriverPayload) -> Result<PdfOutput> {
+        self.pdf_with_progress(payload, Progress::default()).await
+    }
+
+    pub async fn pdf_with_progress(
+        &self,
+        payload: DriverPayload,
+        progress: Progress,
+    ) -> Result<PdfOutput> {
+        let payload = self.resolve_template(payload)?;
+        let disposition = payload
+            .filename
+            .as_deref()
+            .and_then(|filename| content_disposition(filename, payload.disposition));
+        let mut output = self.render(payload, progress).await?;
+        output.content_disposition = disposition;
+        Ok(output)
+    }
+
+    /// Render on the driver `payload` names, then on its fallback if that fails
+    async fn render(&self, payload: DriverPayload, progress: Progress) -> Result<PdfOutput> {
+        let driver = self.get(&payload.driver).ok_or_else(|| {
+            PdfError::Validation(vec![ValidationError::new(
+                "driver",
+                format!("unknown driver `{}`", payload.driver),
+            )])
+        })?;
+
+        let Some((fallback_name, fallback)) = self
+            .fallbacks
+            .get(&payload.driver)
+            .and_then(|name| Some((name, self.get(name)?)))
+        else {
+            return driver.pdf(payload.payload, progress).await;
+        };
+
+        match driver.pdf(payload.payload.clone(), progress.clone()).await {
+            Err(e) if falls_back(&e) => {
+                eprintln!(
+                    "Driver `{}` failed, retrying on fallback driver `{}`: {:#}",
+                    payload.driver, fallback_name, e
+                );
+                self.fallbacks_used.fetch_add(1, Ordering::Relaxed);
+                fallback
+                    .pdf(payload.payload, progress)
+                    .await
+                    .wrap_err_with(|| format!("Fallback driver `{}` failed too", fallback_name))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Whether a render that failed with `e` is worth retrying on the fallback driver
+fn falls_back(e: &color_eyre::eyre::Report) -> bool {
+    e.downcast_ref::<PdfError>().is_none()
+        && e.downcast_ref::<PoolFull>().is_none()
+        && !e
+            .chain()
+            .any(|cause| cause.is::<tokio::time::error::Elapsed>())
+}
@@ -0,0 +1,72 @@
+use axum::http::HeaderMap;
+use color_eyre::eyre::{Context, Result};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{SdkTracerProvider, span_processor_with_async_runtime::BatchSpanProcessor},
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Install `subscriber`, exporting spans over OTLP/HTTP too when an endpoint is configured.
+///
+/// The exporter reads `OTEL_EXPORTER_OTLP_ENDPOINT` (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`)
+/// and the other standard `OTEL_*` variables; without an endpoint only `subscriber` is installed.
+/// Keep the returned provider around and shut it down to flush pending spans.
+pub fn init<S>(subscriber: S) -> Result<Option<SdkTracerProvider>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span> + Send + Sync + 'static,
+{
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|var| std::env::var_os(var).is_some());
+    if !configured {
+        subscriber
+            .try_init()
+            .wrap_err("Failed to install tracing subscriber")?;
+        return Ok(None);
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .wrap_err("Failed to build OTLP exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_span_processor(BatchSpanProcessor::builder(exporter, runtime::Tokio).build())
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    subscriber
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("pdfan")))
+        .try_init()
+        .wrap_err("Failed to install tracing subscriber")?;
+
+    Ok(Some(provider))
+}
+
+/// Make `span` continue the trace from the request's `traceparent` header, if any
+pub fn set_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let cx =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    // Only fails when no subscriber was installed, in which case there's nothing to link
+    let _ = span.set_parent(cx);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
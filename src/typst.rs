@@ -0,0 +1,210 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Context, Result};
+use comemo::Prehashed;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use typst::diag::{FileError, FileResult, Severity, SourceDiagnostic};
+use typst::eval::Tracer;
+use typst::foundations::{Bytes, Datetime, Smart};
+use typst::syntax::{FileId, Source, VirtualPath};
+use typst::text::{Font, FontBook};
+use typst::{Library, World};
+
+use crate::chrome::{DEFAULT_MAX_HTML_BYTES, PdfDriver};
+use crate::error::{PdfError, ValidationError};
+use crate::output::PdfOutput;
+
+/// Typst markup to compile into a PDF
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TypstDriverPdfPayload {
+    /// Source of the document's single file, e.g. `= Hello`
+    pub content: String,
+}
+
+/// Compiles Typst markup in process, with the fonts bundled into the binary.
+///
+/// Documents can't read files or import packages, having only their own source.
+#[derive(Clone)]
+pub struct TypstDriver {
+    resources: Arc<Resources>,
+    max_content_bytes: usize,
+}
+
+/// Standard library and fonts, loaded once and shared by every compilation
+struct Resources {
+    library: Prehashed<Library>,
+    book: Prehashed<FontBook>,
+    fonts: Vec<Font>,
+}
+
+impl Default for TypstDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypstDriver {
+    pub fn new() -> Self {
+        let fonts: Vec<Font> = typst_assets::fonts()
+            .flat_map(|data| Font::iter(Bytes::from_static(data)))
+            .collect();
+        Self {
+            resources: Arc::new(Resources {
+                library: Prehashed::new(Library::default()),
+                book: Prehashed::new(FontBook::from_fonts(&fonts)),
+                fonts,
+            }),
+            max_content_bytes: DEFAULT_MAX_HTML_BYTES,
+        }
+    }
+
+    /// Reject markup above `bytes`, like Chrome's `max_html_bytes`
+    pub fn with_max_content_bytes(mut self, bytes: usize) -> Self {
+        self.max_content_bytes = bytes;
+        self
+    }
+
+    fn compile(resources: &Resources, content: String) -> Result<Vec<u8>> {
+        let world = SingleFileWorld {
+            resources,
+            main: Source::new(FileId::new(None, VirtualPath::new("main.typ")), content),
+        };
+        let mut tracer = Tracer::new();
+        let document = typst::compile(&world, &mut tracer);
+        // Memoized layouts only help recompiling the same document, which a server rarely does
+        comemo::evict(10);
+
+        let document = document.map_err(|errors| PdfError::BadContent(world.describe(&errors)))?;
+        Ok(typst_pdf::pdf(&document, Smart::Auto, None))
+    }
+}
+
+impl PdfDriver for TypstDriver {
+    type Payload = TypstDriverPdfPayload;
+
+    async fn pdf(&self, payload: Self::Payload) -> Result<PdfOutput> {
+        if payload.content.len() > self.max_content_bytes {
+            return Err(PdfError::Validation(vec![ValidationError::new(
+                "content",
+                format!(
+                    "{} bytes is more than the limit of {} bytes",
+                    payload.content.len(),
+                    self.max_content_bytes
+                ),
+            )])
+            .into());
+        }
+        let resources = Arc::clone(&self.resources);
+        let pdf = tokio::task::spawn_blocking(move || Self::compile(&resources, payload.content))
+            .await
+            .wrap_err("Typst compilation panicked")??;
+        Ok(PdfOutput::new(pdf))
+    }
+
+    fn capabilities(&self) -> serde_json::Value {
+        serde_json::json!({
+            "fonts": self
+                .resources
+                .book
+                .families()
+                .map(|(family, _)| family)
+                .collect::<Vec<_>>(),
+            "maxContentBytes": self.max_content_bytes,
+        })
+    }
+}
+
+/// A world of one source file, with no other files or packages to load
+struct SingleFileWorld<'a> {
+    resources: &'a Resources,
+    main: Source,
+}
+
+impl SingleFileWorld<'_> {
+    /// Errors as `line:column: message` with any hints, lines and columns counted from 1
+    fn describe(&self, errors: &[SourceDiagnostic]) -> String {
+        errors
+            .iter()
+            .filter(|error| error.severity == Severity::Error)
+            .map(|error| {
+                let position = self
+                    .main
+                    .range(error.span)
+                    .and_then(|range| {
+                        let line = self.main.byte_to_line(range.start)?;
+                        let column = self.main.byte_to_column(range.start)?;
+                        Some(format!("{}:{}: ", line + 1, column + 1))
+                    })
+                    .unwrap_or_default();
+                let mut message = format!("{}{}", position, error.message);
+                for hint in &error.hints {
+                    message.push_str(&format!(" (hint: {})", hint));
+                }
+                message
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl World for SingleFileWorld<'_> {
+    fn library(&self) -> &Prehashed<Library> {
+        &self.resources.library
+    }
+
+    fn book(&self) -> &Prehashed<FontBook> {
+        &self.resources.book
+    }
+
+    fn main(&self) -> Source {
+        self.main.clone()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main.id() {
+            Ok(self.main.clone())
+        } else {
+            Err(FileError::AccessDenied)
+        }
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        let _ = id;
+        Err(FileError::AccessDenied)
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.resources.fonts.get(index).cloned()
+    }
+
+    fn today(&self, offset: Option<i64>) -> Option<Datetime> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        // Without an offset, the server's local time is taken to be UTC
+        let days = (secs + offset.unwrap_or(0) * 3600).div_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        Datetime::from_ymd(year, month, day)
+    }
+}
+
+/// Gregorian date of the day `days` after 1970-01-01
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    // Howard Hinnant's algorithm, counting 400-year eras from 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u8;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u8;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year as i32, month, day)
+}
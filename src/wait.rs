@@ -9,7 +9,7 @@ use chromiumoxide::{
     },
     cdp::js_protocol::runtime::EventBindingCalled,
 };
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, eyre};
 use futures::StreamExt;
 use tokio::sync::mpsc;
 
@@ -111,13 +111,11 @@ pub async fn wait_for_network_idle(page: &Page, kind: NetworkIdleKind) -> Result
     Ok(())
 }
 
-/// Wait for a custom event triggered by calling `window.finishRendering()`.
-///
-/// This sets up a binding so that the page can signal when it's ready for PDF generation.
-/// The page should dispatch a 'prerender-trigger' event, or call `window.finishRendering()` directly.
+/// Install the `window.finishRendering()` binding and the 'prerender-trigger' listener.
 ///
-/// Must be called BEFORE navigation (goto).
-pub async fn setup_custom_event_wait(page: &Page) -> Result<impl std::future::Future<Output = Result<()>>> {
+/// Both stay on the page for every later navigation, and CDP rejects exposing the
+/// same function twice, so this must run only once per page.
+pub async fn install_render_binding(page: &Page) -> Result<()> {
     // Expose the finishRendering function
     page.expose_function("finishRendering", "function() {}").await?;
 
@@ -131,6 +129,16 @@ pub async fn setup_custom_event_wait(page: &Page) -> Result<impl std::future::Fu
         "#.to_string()
     ).await?;
 
+    Ok(())
+}
+
+/// Wait for a custom event triggered by calling `window.finishRendering()`.
+///
+/// The page should dispatch a 'prerender-trigger' event, or call `window.finishRendering()` directly.
+/// Requires `install_render_binding` to have run on the page.
+///
+/// Must be called BEFORE navigation (goto).
+pub async fn setup_custom_event_wait(page: &Page) -> Result<impl std::future::Future<Output = Result<()>>> {
     let mut binding_events = page.event_listener::<EventBindingCalled>().await?;
 
     Ok(async move {
@@ -142,3 +150,122 @@ pub async fn setup_custom_event_wait(page: &Page) -> Result<impl std::future::Fu
         Ok(())
     })
 }
+
+/// Binding the DOM observer calls on every batch of mutations
+const DOM_MUTATED_BINDING: &str = "pdfanDomMutated";
+
+/// Install the binding `wait_for_dom_stable` listens on.
+///
+/// Like `install_render_binding`, this must run only once per page.
+pub async fn install_dom_mutation_binding(page: &Page) -> Result<()> {
+    page.expose_function(DOM_MUTATED_BINDING, "function() {}").await?;
+    Ok(())
+}
+
+/// Wait until the DOM hasn't changed for `quiet_period`, e.g. after a client-side render.
+///
+/// A MutationObserver reports every change through the binding from
+/// `install_dom_mutation_binding`, which must have run on the page. Fails if the DOM is
+/// still changing after `timeout`, as it does on pages with a ticking clock.
+pub async fn wait_for_dom_stable(page: &Page, quiet_period: Duration, timeout: Duration) -> Result<()> {
+    let mut binding_events = page.event_listener::<EventBindingCalled>().await?;
+
+    // Observing stays on for the rest of the page's life, so it is only started once.
+    // Documents loaded before the binding was installed only have Chrome's raw binding,
+    // which ignores calls without a string argument.
+    page.evaluate(format!(
+        r#"(() => {{
+            if (window.__pdfanDomObserver) return;
+            window.__pdfanDomObserver = new MutationObserver(() => window.{DOM_MUTATED_BINDING}(''));
+            window.__pdfanDomObserver.observe(document, {{
+                subtree: true, childList: true, attributes: true, characterData: true,
+            }});
+        }})()"#
+    ))
+    .await?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut quiet_until = tokio::time::Instant::now() + quiet_period;
+
+    loop {
+        tokio::select! {
+            event = binding_events.next() => match event {
+                Some(event) if event.name == DOM_MUTATED_BINDING => {
+                    quiet_until = tokio::time::Instant::now() + quiet_period;
+                }
+                Some(_) => {}
+                None => return Err(eyre!("Page closed while waiting for the DOM to settle")),
+            },
+            _ = tokio::time::sleep_until(quiet_until) => return Ok(()),
+            _ = tokio::time::sleep_until(deadline) => {
+                return Err(eyre!("DOM kept changing for {:?}", timeout));
+            }
+        }
+    }
+}
+
+/// Wait until an element matching `selector` exists in the page.
+///
+/// Polls every 100ms, so it also works across navigations (e.g. after submitting a form).
+pub async fn wait_for_selector(page: &Page, selector: &str) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    while page.find_element(selector).await.is_err() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Wait until the JS expression `expression` evaluates to a truthy value.
+///
+/// Polls every 100ms. Promises are awaited, and evaluation errors (e.g. the page is
+/// still loading the script the expression relies on) count as not ready yet; the last
+/// one is reported if `timeout` runs out.
+pub async fn wait_for_expression(page: &Page, expression: &str, timeout: Duration) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let last_error = match page.evaluate(expression).await {
+            Ok(result) if result.value().is_some_and(is_truthy) => return Ok(()),
+            Ok(_) => None,
+            Err(e) => Some(e),
+        };
+
+        if tokio::time::Instant::now() + POLL_INTERVAL > deadline {
+            return Err(match last_error {
+                Some(e) => eyre!("Expression did not become truthy within {:?}, last error: {}", timeout, e),
+                None => eyre!("Expression did not become truthy within {:?}", timeout),
+            });
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Wait until no CSS animation or transition is running, e.g. content fading in.
+///
+/// Polls every 100ms. Animations that repeat forever, like spinners, are ignored since
+/// they never finish.
+pub async fn wait_for_animations(page: &Page, timeout: Duration) -> Result<()> {
+    const SETTLED: &str = r#"
+        document.getAnimations().every(a =>
+            a.playState !== 'running' || a.effect?.getTiming().iterations === Infinity)
+    "#;
+
+    wait_for_expression(page, SETTLED, timeout)
+        .await
+        .map_err(|_| eyre!("Animations did not settle within {:?}", timeout))
+}
+
+/// JS truthiness of a value returned by `evaluate`
+fn is_truthy(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(b) => *b,
+        serde_json::Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0 && !n.is_nan()),
+        serde_json::Value::String(s) => !s.is_empty(),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => true,
+    }
+}
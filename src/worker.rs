@@ -1,27 +1,98 @@
-use color_eyre::eyre::{Context, Result};
-use std::{future::Future, sync::Arc, time::Duration};
-use tokio::sync::{
-    OwnedSemaphorePermit, Semaphore,
-    oneshot::{self},
+use color_eyre::eyre::{Context, Result, eyre};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
+use tokio::{
+    sync::{
+        Mutex, OwnedSemaphorePermit, Semaphore, TryAcquireError,
+        oneshot::{self},
+    },
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{Instrument, Span};
 
 pub trait Task<Ctx> {
     type Result;
     fn process(&self, ctx: &mut Ctx) -> impl Future<Output = Self::Result> + std::marker::Send;
+
+    /// Whether `result` counts as a failure in the pool's error rate
+    fn is_failure(result: &Self::Result) -> bool {
+        let _ = result;
+        false
+    }
+}
+
+/// How many recent results the error rate is computed over
+const RECENT_RESULTS: usize = 100;
+
+/// Snapshot of a pool's load
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStats {
+    /// Tasks waiting for a worker
+    pub queued: usize,
+    /// Callers waiting for a free slot before their task can be queued
+    pub waiting: usize,
+    /// Tasks a worker is processing
+    pub in_flight: usize,
+    /// Tasks that can still be queued before callers have to wait
+    pub available_permits: usize,
+    pub workers: usize,
+    /// Share of the last results that failed, from 0 to 1
+    pub recent_error_rate: f64,
+}
+
+/// What `queue` does when `cap` tasks are already queued or running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait in line for a slot, up to the caller's timeout
+    #[default]
+    Wait,
+    /// Fail right away with `PoolFull`
+    Reject,
 }
 
+/// Returned by `queue` under `Backpressure::Reject` when the pool has no free slot
+#[derive(Debug)]
+pub struct PoolFull;
+
+impl std::fmt::Display for PoolFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Worker pool is full")
+    }
+}
+
+impl std::error::Error for PoolFull {}
+
 struct Packet<Ctx, T: Task<Ctx>> {
     task: T,
     tx: oneshot::Sender<T::Result>,
     _permit: OwnedSemaphorePermit,
+    /// Span of the caller, so the task's spans end up in the same trace
+    span: Span,
+    /// Open while the packet sits in the queue
+    queue_span: Span,
 }
 
 impl<Ctx, T: Task<Ctx>> Packet<Ctx, T> {
     fn new(task: T, tx: oneshot::Sender<T::Result>, permit: OwnedSemaphorePermit) -> Self {
+        let span = Span::current();
+        let queue_span = tracing::info_span!(parent: &span, "queue_wait");
+
         Self {
             task,
             tx,
             _permit: permit,
+            span,
+            queue_span,
         }
     }
 
@@ -30,9 +101,25 @@ impl<Ctx, T: Task<Ctx>> Packet<Ctx, T> {
     }
 }
 
+/// Runs tasks on a fixed set of workers.
+///
+/// At most `cap` tasks are queued or running at once: each holds a semaphore permit
+/// until its worker is done with it, and the channel to the workers is bounded to `cap`
+/// as well, so memory stays bounded however many callers flood `queue`. Callers beyond
+/// that either wait for a permit or are turned away, see `Backpressure`.
 pub struct WorkerPool<Ctx, T: Task<Ctx>> {
     tx: async_channel::Sender<Packet<Ctx, T>>,
     semaphore: Arc<Semaphore>,
+    /// Held from acquiring a permit until the packet is sent, so packets enter the
+    /// channel in the order callers arrived rather than the order they were woken
+    admission: Mutex<()>,
+    workers: Vec<JoinHandle<()>>,
+    cap: usize,
+    backpressure: Backpressure,
+    /// Callers in `queue` that haven't handed their packet to the channel yet
+    waiting: AtomicUsize,
+    /// Whether each of the last `RECENT_RESULTS` tasks failed, oldest first
+    recent_failures: Arc<std::sync::Mutex<VecDeque<bool>>>,
 }
 
 impl<T, Ctx> WorkerPool<Ctx, T>
@@ -46,34 +133,151 @@ where
         F: Fn() -> Fut + Send + Sync + Clone + 'static,
         Fut: Future<Output = Result<Ctx>> + Send + 'static,
     {
+        Self::new_staggered(cap, workers, Duration::ZERO, make_ctx)
+    }
+
+    /// Like `new`, but workers create their contexts one at a time, `stagger` apart.
+    ///
+    /// Avoids a burst of context creation at startup. A zero `stagger` starts all
+    /// workers at once, like `new`.
+    pub fn new_staggered<F, Fut>(cap: usize, workers: usize, stagger: Duration, make_ctx: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + Clone + 'static,
+        Fut: Future<Output = Result<Ctx>> + Send + 'static,
+    {
+        let startup = (!stagger.is_zero()).then(|| Stagger {
+            turn: Arc::new(Mutex::new(())),
+            delay: stagger,
+        });
         let semaphore = Arc::new(Semaphore::new(cap));
-        let (tx, rx) = async_channel::unbounded();
+        // Never full: a packet needs a permit, and there are only `cap` of them
+        let (tx, rx) = async_channel::bounded(cap.max(1));
+        let recent_failures = Arc::new(std::sync::Mutex::new(VecDeque::with_capacity(
+            RECENT_RESULTS,
+        )));
 
-        for _ in 0..workers {
-            let rx = rx.clone();
-            let make_ctx = make_ctx.clone();
-            tokio::spawn(spawn_worker(rx, make_ctx));
+        let workers = (0..workers)
+            .map(|_| {
+                let rx = rx.clone();
+                let make_ctx = make_ctx.clone();
+                tokio::spawn(supervise_worker(
+                    rx,
+                    make_ctx,
+                    startup.clone(),
+                    Arc::clone(&recent_failures),
+                ))
+            })
+            .collect();
+
+        Self {
+            tx,
+            semaphore,
+            admission: Mutex::new(()),
+            workers,
+            cap,
+            backpressure: Backpressure::default(),
+            waiting: AtomicUsize::new(0),
+            recent_failures,
         }
+    }
+
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Current load of the pool
+    pub fn stats(&self) -> PoolStats {
+        let available_permits = self.semaphore.available_permits();
+        let queued = self.tx.len();
+        // Every queued or running task holds a permit
+        let in_flight = (self.cap - available_permits).saturating_sub(queued);
+
+        let recent = self.recent_failures.lock().unwrap();
+        let recent_error_rate = if recent.is_empty() {
+            0.0
+        } else {
+            recent.iter().filter(|failed| **failed).count() as f64 / recent.len() as f64
+        };
+
+        PoolStats {
+            queued,
+            waiting: self.waiting.load(Ordering::Relaxed),
+            in_flight,
+            available_permits,
+            workers: self.workers.len(),
+            recent_error_rate,
+        }
+    }
 
-        Self { tx, semaphore }
+    /// Roughly how many tasks a task queued now would wait behind, 0 meaning it is next.
+    ///
+    /// Counts callers waiting for a slot and tasks waiting for a worker. Best effort:
+    /// the numbers are read separately while other callers come and go.
+    pub fn queue_position(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed) + self.tx.len()
     }
 
+    /// Stop accepting tasks and wait for the workers to drain the queue.
+    ///
+    /// Tasks already queued are still processed; new `queue` calls fail once the
+    /// semaphore is closed.
+    pub async fn shutdown(self) {
+        self.semaphore.close();
+        self.tx.close();
+
+        for worker in self.workers {
+            if let Err(e) = worker.await {
+                eprintln!("Worker failed during shutdown: {:?}", e);
+            }
+        }
+    }
+
+    /// Queue `task` and wait up to `timeout` for its result.
+    ///
+    /// Tasks are handed to workers in the order `queue` was first polled, also while
+    /// the pool is full: callers wait their turn in line instead of racing for the
+    /// next free slot. With several workers, tasks start in that order but may finish
+    /// out of order. Tasks whose caller gave up before a worker got to them are skipped.
+    ///
+    /// Under `Backpressure::Reject`, a full pool fails the call with `PoolFull` instead.
     pub async fn queue(&self, task: T, timeout: Duration) -> Result<T::Result> {
-        tokio::time::timeout(timeout, async {
-            let permit = self
-                .semaphore
-                .clone()
-                .acquire_owned()
-                .await
-                .wrap_err("Pool shut down")?;
-
-            let (tx, rx) = oneshot::channel();
-            let packet = Packet::new(task, tx, permit);
-
-            self.tx
-                .send(packet)
-                .await
-                .wrap_err("Could not send to worker queue")?;
+        self.queue_until(task, Instant::now() + timeout).await
+    }
+
+    /// Queue `task` and wait for its result until `deadline`, queue time included.
+    ///
+    /// Keeps the same ordering as `queue`.
+    pub async fn queue_until(&self, task: T, deadline: Instant) -> Result<T::Result> {
+        tokio::time::timeout_at(deadline, async {
+            let rx = {
+                let _waiting = WaitingGuard::new(&self.waiting);
+                // Tokio's mutex is fair, so this is the line callers wait in
+                let _admission = self.admission.lock().await;
+
+                let permit = match self.backpressure {
+                    Backpressure::Wait => self
+                        .semaphore
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .wrap_err("Pool shut down")?,
+                    Backpressure::Reject => match self.semaphore.clone().try_acquire_owned() {
+                        Ok(permit) => permit,
+                        Err(TryAcquireError::NoPermits) => return Err(PoolFull.into()),
+                        Err(TryAcquireError::Closed) => return Err(eyre!("Pool shut down")),
+                    },
+                };
+
+                let (tx, rx) = oneshot::channel();
+                let packet = Packet::new(task, tx, permit);
+
+                self.tx
+                    .send(packet)
+                    .await
+                    .wrap_err("Could not send to worker queue")?;
+                rx
+            };
             rx.await.wrap_err("Worker dropped")
         })
         .await
@@ -81,19 +285,118 @@ where
     }
 }
 
-async fn spawn_worker<T, Ctx, F, Fut>(rx: async_channel::Receiver<Packet<Ctx, T>>, make_ctx: F)
-where
+/// Counts a caller as waiting until dropped, also when the caller gives up
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl<'a> WaitingGuard<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Serializes worker startup
+#[derive(Clone)]
+struct Stagger {
+    turn: Arc<Mutex<()>>,
+    delay: Duration,
+}
+
+/// Run a worker until the queue closes, replacing it with a fresh one whenever it panics.
+///
+/// The task a worker panicked on fails with "Worker dropped"; the pool keeps its capacity.
+async fn supervise_worker<T, Ctx, F, Fut>(
+    rx: async_channel::Receiver<Packet<Ctx, T>>,
+    make_ctx: F,
+    startup: Option<Stagger>,
+    recent_failures: Arc<std::sync::Mutex<VecDeque<bool>>>,
+) where
+    T: Task<Ctx> + Send + Sync + 'static,
+    T::Result: Send + 'static,
+    Ctx: Send + 'static,
+    F: Fn() -> Fut + Send + Sync + Clone + 'static,
+    Fut: Future<Output = Result<Ctx>> + Send + 'static,
+{
+    loop {
+        let worker = tokio::spawn(spawn_worker(
+            rx.clone(),
+            make_ctx.clone(),
+            startup.clone(),
+            Arc::clone(&recent_failures),
+        ));
+        match worker.await {
+            Err(e) if e.is_panic() && !rx.is_closed() => {
+                eprintln!("Worker panicked, starting a new one: {:?}", e);
+            }
+            _ => break,
+        }
+    }
+}
+
+async fn spawn_worker<T, Ctx, F, Fut>(
+    rx: async_channel::Receiver<Packet<Ctx, T>>,
+    make_ctx: F,
+    startup: Option<Stagger>,
+    recent_failures: Arc<std::sync::Mutex<VecDeque<bool>>>,
+) where
     T: Task<Ctx>,
     F: Fn() -> Fut,
     Fut: Future<Output = Result<Ctx>>,
 {
-    let mut ctx = make_ctx().await.unwrap();
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
 
-    while let Ok(packet) = rx.recv().await {
+    // Keep trying rather than leave the pool a worker short
+    let mut retry_delay = Duration::from_secs(1);
+    let mut ctx = loop {
+        let ctx = match &startup {
+            Some(stagger) => {
+                // Hold the turn through the delay so the next worker starts that much later
+                let _turn = stagger.turn.lock().await;
+                let ctx = make_ctx().await;
+                tokio::time::sleep(stagger.delay).await;
+                ctx
+            }
+            None => make_ctx().await,
+        };
+        match ctx {
+            Ok(ctx) => break ctx,
+            Err(e) if !rx.is_closed() => {
+                eprintln!(
+                    "Failed to start worker, retrying in {:?}: {:?}",
+                    retry_delay, e
+                );
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+            Err(_) => return,
+        }
+    };
+
+    while let Ok(mut packet) = rx.recv().await {
+        // Replacing the span closes it, ending the queue wait
+        packet.queue_span = Span::none();
         if packet.tx.is_closed() {
             continue;
         }
-        let result = packet.task.process(&mut ctx).await;
+        let result = packet
+            .task
+            .process(&mut ctx)
+            .instrument(packet.span.clone())
+            .await;
+
+        {
+            let mut recent = recent_failures.lock().unwrap();
+            if recent.len() == RECENT_RESULTS {
+                recent.pop_front();
+            }
+            recent.push_back(T::is_failure(&result));
+        }
         let _ = packet.send(result);
     }
 }
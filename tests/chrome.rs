@@ -0,0 +1,343 @@
+//! Chrome-backed integration tests.
+//!
+//! These launch a real browser and are ignored by default; run them with
+//! `cargo test -- --ignored` on a machine with Chrome or Chromium installed.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use pdfan::chrome::{ChromeDriver, ChromeDriverConfig, ChromeDriverPdfPayload, PdfDriver};
+use pdfan::error::PdfError;
+use serde_json::json;
+
+/// Serve the HTML fixtures on a random local port
+async fn serve_fixtures() -> SocketAddr {
+    async fn slow_image() -> impl IntoResponse {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        (
+            [(header::CONTENT_TYPE, "image/svg+xml")],
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10" fill="red"/></svg>"#,
+        )
+    }
+
+    fn html(body: &'static str) -> impl IntoResponse {
+        ([(header::CONTENT_TYPE, "text/html")], body)
+    }
+
+    let app = Router::new()
+        .route(
+            "/two-pages.html",
+            get(|| async { html(include_str!("fixtures/two-pages.html")) }),
+        )
+        .route(
+            "/delayed-event.html",
+            get(|| async { html(include_str!("fixtures/delayed-event.html")) }),
+        )
+        .route(
+            "/slow-images.html",
+            get(|| async { html(include_str!("fixtures/slow-images.html")) }),
+        )
+        .route("/slow-image.svg", get(slow_image));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+async fn driver() -> ChromeDriver {
+    ChromeDriver::new(ChromeDriverConfig::default())
+        .await
+        .expect("Failed to launch Chrome")
+}
+
+fn payload(value: serde_json::Value) -> ChromeDriverPdfPayload {
+    serde_json::from_value(value).expect("Invalid payload")
+}
+
+fn page_count(pdf: &[u8]) -> usize {
+    assert!(pdf.starts_with(b"%PDF-"), "output is not a PDF");
+    lopdf::Document::load_mem(pdf)
+        .expect("Failed to parse PDF")
+        .get_pages()
+        .len()
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn renders_html() {
+    let driver = driver().await;
+
+    let output = driver
+        .pdf(payload(
+            json!({ "html": include_str!("fixtures/two-pages.html") }),
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(page_count(&output.pdf), 2);
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn renders_url() {
+    let addr = serve_fixtures().await;
+    let driver = driver().await;
+
+    let output = driver
+        .pdf(payload(
+            json!({ "url": format!("http://{addr}/two-pages.html") }),
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(page_count(&output.pdf), 2);
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn waits_for_custom_event() {
+    let addr = serve_fixtures().await;
+    let driver = driver().await;
+
+    let output = driver
+        .pdf(payload(json!({
+            "url": format!("http://{addr}/delayed-event.html"),
+            "waitForEvent": true,
+        })))
+        .await
+        .unwrap();
+
+    assert_eq!(page_count(&output.pdf), 1);
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn waits_for_slow_images() {
+    let addr = serve_fixtures().await;
+    let driver = driver().await;
+
+    let output = driver
+        .pdf(payload(json!({
+            "url": format!("http://{addr}/slow-images.html"),
+            "waitForResources": true,
+        })))
+        .await
+        .unwrap();
+
+    assert_eq!(page_count(&output.pdf), 1);
+}
+
+/// Whether the first page paints with pure red, the fixture's background color
+fn paints_red(pdf: &[u8]) -> bool {
+    let doc = lopdf::Document::load_mem(pdf).expect("Failed to parse PDF");
+    let (_, page_id) = doc
+        .get_pages()
+        .into_iter()
+        .next()
+        .expect("PDF has no pages");
+    let content = doc
+        .get_and_decode_page_content(page_id)
+        .expect("Failed to decode page content");
+
+    content.operations.iter().any(|op| {
+        matches!(op.operator.as_str(), "rg" | "sc" | "scn")
+            && op
+                .operands
+                .iter()
+                .map(|o| o.as_float().unwrap_or(-1.0))
+                .eq([1.0, 0.0, 0.0])
+    })
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn print_background_matrix() {
+    let driver = driver().await;
+
+    for media in ["print", "screen"] {
+        for print_background in [true, false] {
+            let output = driver
+                .pdf(payload(json!({
+                    "html": include_str!("fixtures/print-background.html"),
+                    "media": media,
+                    "printBackground": print_background,
+                })))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                paints_red(&output.pdf),
+                print_background,
+                "media {media}, printBackground {print_background}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn waits_for_custom_event_on_reused_pages() {
+    let addr = serve_fixtures().await;
+    let driver = driver().await;
+
+    // More renders than workers, so at least one page serves two of them
+    for _ in 0..5 {
+        let output = driver
+            .pdf(payload(json!({
+                "url": format!("http://{addr}/delayed-event.html"),
+                "waitForEvent": true,
+            })))
+            .await
+            .unwrap();
+
+        assert_eq!(page_count(&output.pdf), 1);
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn fails_fast_on_refused_connection() {
+    // Bind and release a port so nothing listens on it
+    let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let driver = driver().await;
+
+    let started = std::time::Instant::now();
+    let err = driver
+        .pdf(payload(json!({ "url": format!("http://{addr}/") })))
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err.downcast_ref::<PdfError>(),
+            Some(PdfError::Navigation { .. })
+        ),
+        "unexpected error: {err:?}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn rejects_html_over_the_limit() {
+    let driver = ChromeDriver::new(ChromeDriverConfig {
+        max_html_bytes: 1024,
+        ..ChromeDriverConfig::default()
+    })
+    .await
+    .expect("Failed to launch Chrome");
+
+    let html = format!("<p>{}</p>", "x".repeat(2048));
+    let err = driver
+        .pdf(payload(json!({ "html": html })))
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err.downcast_ref::<PdfError>(),
+            Some(PdfError::Validation(errors)) if errors[0].field == "html"
+        ),
+        "unexpected error: {err:?}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn fails_fast_on_html_that_never_loads() {
+    let driver = driver().await;
+
+    let started = std::time::Instant::now();
+    let err = driver
+        .pdf(payload(
+            json!({ "html": "<script>while (true) {}</script>" }),
+        ))
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err.downcast_ref::<PdfError>(),
+            Some(PdfError::BadContent(_))
+        ),
+        "unexpected error: {err:?}"
+    );
+    // Not retried, which would run into the task timeout
+    assert!(started.elapsed() < Duration::from_secs(20));
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn reports_print_timeouts() {
+    let driver = ChromeDriver::new(ChromeDriverConfig {
+        print_timeout: Some(Duration::from_millis(50)),
+        ..ChromeDriverConfig::default()
+    })
+    .await
+    .unwrap();
+
+    // Thousands of pages of table rows take Chrome seconds to lay out for print
+    let rows = "<tr><td>cell</td><td>cell</td><td>cell</td></tr>".repeat(200_000);
+    let err = driver
+        .pdf(payload(json!({ "html": format!("<table>{rows}</table>") })))
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err.downcast_ref::<PdfError>(),
+            Some(PdfError::PrintTimeout(_))
+        ),
+        "unexpected error: {err:?}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn rejects_headers_taller_than_their_margin() {
+    let driver = driver().await;
+
+    let err = driver
+        .pdf(payload(json!({
+            "html": "<p>Body</p>",
+            "headerTemplate": "<div style=\"height: 2in\">Tall header</div>",
+            "marginTop": 0.4,
+            "templateFit": "error",
+        })))
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err.downcast_ref::<PdfError>(),
+            Some(PdfError::Validation(errors)) if errors[0].field == "marginTop"
+        ),
+        "unexpected error: {err:?}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires Chrome"]
+async fn captures_png_screenshots() {
+    let driver = driver().await;
+
+    let output = driver
+        .pdf(payload(json!({
+            "html": include_str!("fixtures/two-pages.html"),
+            "outputFormat": "png",
+        })))
+        .await
+        .unwrap();
+
+    assert!(
+        output.pdf.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "output is not a PNG"
+    );
+    assert_eq!(output.format.content_type(), "image/png");
+}
@@ -0,0 +1,39 @@
+//! Coalescing tests against the mock driver, no browser needed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use pdfan::coalesce::Coalescer;
+use pdfan::mock::MockPdfDriver;
+use pdfan::registry::{DriverPayload, DriverRegistry};
+use serde_json::json;
+
+fn payload(value: serde_json::Value) -> DriverPayload {
+    serde_json::from_value(value).expect("Invalid payload")
+}
+
+#[tokio::test]
+async fn identical_requests_share_one_render() {
+    let mut registry = DriverRegistry::new();
+    registry.register(
+        "chrome",
+        MockPdfDriver::new().with_delay(Duration::from_millis(100)),
+    );
+    let registry = Arc::new(registry);
+    let coalescer = Coalescer::new();
+
+    let html = json!({ "html": "<p>hi</p>" });
+    let (first, second) = tokio::join!(
+        coalescer.pdf(Arc::clone(&registry), "key".into(), payload(html.clone())),
+        coalescer.pdf(Arc::clone(&registry), "key".into(), payload(html.clone())),
+    );
+    let shared = first.unwrap();
+    assert!(Arc::ptr_eq(&shared, &second.unwrap()));
+
+    // Once finished, the render isn't handed to later requests
+    let later = coalescer
+        .pdf(Arc::clone(&registry), "key".into(), payload(html))
+        .await
+        .unwrap();
+    assert!(!Arc::ptr_eq(&shared, &later));
+}
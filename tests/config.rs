@@ -0,0 +1,78 @@
+//! Config file tests, no browser needed.
+
+use std::path::PathBuf;
+
+use pdfan::config::Config;
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("pdfan-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn reads_a_config_file() {
+    let path = write_config(
+        "config",
+        r#"
+            max_pending = 20
+            render_mode = "untrusted"
+            print_timeout_ms = 5000
+
+            [admission]
+            budget_ms = 4000
+            costs.wait_for_event_ms = 6000
+
+            [format_margins.a4]
+            top = 1.0
+            right = 0.5
+            bottom = 1.0
+            left = 0.5
+        "#,
+    );
+    let config = Config::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(config.max_pending(), 20);
+    let chrome = config.chrome_driver_config();
+    assert_eq!(chrome.format_margins["A4"].top, 1.0);
+    assert_eq!(chrome.print_timeout.unwrap().as_millis(), 5000);
+    let admission = chrome.admission.unwrap();
+    assert_eq!(admission.costs.wait_for_event_ms, 6000);
+    // Costs the file leaves out keep their defaults
+    assert_eq!(admission.costs.url_ms, 1000);
+}
+
+#[test]
+fn rejects_unknown_keys() {
+    let path = write_config("unknown", "max_pendng = 20\n");
+    let err = Config::from_file(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(format!("{err:#}").contains("max_pendng"), "{err:#}");
+}
+
+// The only test touching env vars, since they are shared by every test in this binary
+#[test]
+fn env_vars_override_the_file() {
+    let path = write_config("env", "max_pending = 20\nmin_scale = 0.5\n");
+    unsafe { std::env::set_var("PDFAN_MAX_PENDING", "5") };
+    let config = Config::load(Some(&path));
+    unsafe { std::env::set_var("PDFAN_SCALE_OUT_OF_RANGE", "clmap") };
+    let typo = Config::load(Some(&path));
+    unsafe {
+        std::env::remove_var("PDFAN_MAX_PENDING");
+        std::env::remove_var("PDFAN_SCALE_OUT_OF_RANGE");
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    let config = config.unwrap();
+    assert_eq!(config.max_pending(), 5);
+    // Keys without an env var set keep the file's value
+    assert_eq!(config.scale_policy().min, 0.5);
+    let err = typo.unwrap_err();
+    assert!(
+        format!("{err:#}").contains("PDFAN_SCALE_OUT_OF_RANGE"),
+        "{err:#}"
+    );
+}
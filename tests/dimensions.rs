@@ -0,0 +1,51 @@
+//! Paper dimension parsing, no browser needed.
+
+use pdfan::chrome::parse_dimension;
+
+fn assert_inches(dimension: &str, inches: f64) {
+    let parsed = parse_dimension(dimension).unwrap();
+    assert!(
+        (parsed - inches).abs() < 1e-9,
+        "{dimension} parsed as {parsed}in, expected {inches}in"
+    );
+}
+
+#[test]
+fn converts_each_unit_to_inches() {
+    assert_inches("254mm", 10.0);
+    assert_inches("25.4cm", 10.0);
+    assert_inches("8.5in", 8.5);
+    assert_inches("960px", 10.0);
+    assert_inches("720pt", 10.0);
+    assert_inches("11", 11.0);
+    assert_inches(" 210 mm ", 210.0 / 25.4);
+}
+
+#[test]
+fn rejects_invalid_dimensions() {
+    for dimension in ["", "mm", "210pc", "wide", "-5in", "0", "NaNin"] {
+        let err = parse_dimension(dimension).unwrap_err();
+        assert!(
+            err.to_string().contains("dimension"),
+            "{dimension}: unexpected error {err}"
+        );
+    }
+}
+
+#[test]
+fn reads_a4_in_points_as_a4() {
+    let width = parse_dimension("595.28pt").unwrap();
+    let height = parse_dimension("841.89pt").unwrap();
+    assert!((width - 210.0 / 25.4).abs() < 1e-3, "{width}");
+    assert!((height - 297.0 / 25.4).abs() < 1e-3, "{height}");
+}
+
+#[test]
+fn rejects_sizes_chrome_cannot_print() {
+    assert_inches("200in", 200.0);
+    assert_inches("0.1in", 0.1);
+    for dimension in ["100000in", "201in", "0.05in", "1px"] {
+        let err = parse_dimension(dimension).unwrap_err();
+        assert!(err.to_string().contains("between"), "{dimension}: {err}");
+    }
+}
@@ -0,0 +1,34 @@
+//! Job store tests against the mock driver, no browser needed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use pdfan::jobs::{JobStatus, JobStore};
+use pdfan::mock::MockPdfDriver;
+use pdfan::registry::DriverRegistry;
+use serde_json::json;
+
+#[tokio::test]
+async fn cancels_an_unfinished_job() {
+    let mut registry = DriverRegistry::new();
+    registry.register(
+        "chrome",
+        MockPdfDriver::new().with_delay(Duration::from_secs(5)),
+    );
+    let jobs = Arc::new(JobStore::new(Duration::from_secs(60)));
+
+    let id = jobs.submit(
+        Arc::new(registry),
+        reqwest::Client::new(),
+        serde_json::from_value(json!({ "html": "<p>hi</p>" })).unwrap(),
+        None,
+        None,
+    );
+    let job = jobs.cancel(&id).unwrap();
+    assert_eq!(job.status, JobStatus::Cancelled);
+
+    // The render is dropped, so the job never finishes after all
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(jobs.get(&id).unwrap().status, JobStatus::Cancelled);
+    assert!(jobs.cancel("missing").is_none());
+}
@@ -0,0 +1,22 @@
+//! Markdown conversion, no browser needed.
+
+use pdfan::markdown::{find_theme, to_html};
+
+#[test]
+fn converts_tables_and_highlights_code() {
+    let markdown = "| a | b |\n|---|---|\n| 1 | 2 |\n\n```rust\nfn main() {}\n```\n";
+
+    let html = to_html(markdown, find_theme("Default").unwrap()).unwrap();
+
+    assert!(html.contains("<table>"));
+    assert!(html.contains("<td>1</td>"));
+    // Highlighted code is styled inline, one span per token
+    assert!(html.contains("<pre style="));
+    assert!(html.contains("<span style="));
+}
+
+#[test]
+fn finds_themes_by_name() {
+    assert!(find_theme("serif").is_some());
+    assert!(find_theme("missing").is_none());
+}
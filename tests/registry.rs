@@ -0,0 +1,136 @@
+//! Dispatch tests against the mock driver, no browser needed.
+
+use pdfan::error::PdfError;
+use pdfan::mock::MockPdfDriver;
+use pdfan::registry::{DriverPayload, DriverRegistry};
+use serde_json::json;
+
+fn payload(value: serde_json::Value) -> DriverPayload {
+    serde_json::from_value(value).expect("Invalid payload")
+}
+
+#[tokio::test]
+async fn dispatches_by_driver_name() {
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", MockPdfDriver::new().with_pdf("chrome"));
+    registry.register("other", MockPdfDriver::new().with_pdf("other"));
+
+    let output = registry
+        .pdf(payload(json!({ "driver": "other", "html": "<p>hi</p>" })))
+        .await
+        .unwrap();
+    assert_eq!(output.pdf, b"other");
+
+    let output = registry
+        .pdf(payload(json!({ "html": "<p>hi</p>" })))
+        .await
+        .unwrap();
+    assert_eq!(output.pdf, b"chrome");
+}
+
+#[tokio::test]
+async fn rejects_unknown_driver() {
+    let registry = DriverRegistry::new();
+
+    let err = registry
+        .pdf(payload(json!({ "driver": "missing" })))
+        .await
+        .unwrap_err();
+
+    match err.downcast_ref::<PdfError>() {
+        Some(PdfError::Validation(errors)) => assert_eq!(errors[0].field, "driver"),
+        other => panic!("expected a validation error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn propagates_driver_errors() {
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", MockPdfDriver::new().failing("boom"));
+
+    let err = registry.pdf(payload(json!({}))).await.unwrap_err();
+    assert_eq!(err.to_string(), "boom");
+}
+
+#[tokio::test]
+async fn describes_payloads_per_driver() {
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", MockPdfDriver::new());
+    registry.register("other", MockPdfDriver::new());
+
+    let schema = registry.schema();
+    let branches = schema["oneOf"].as_array().unwrap();
+
+    assert_eq!(branches.len(), 2);
+    // Only payloads for other drivers have to name theirs
+    assert_eq!(branches[0]["allOf"][1]["required"], json!([]));
+    assert_eq!(branches[1]["allOf"][1]["required"], json!(["driver"]));
+    assert_eq!(
+        branches[1]["allOf"][1]["properties"]["driver"]["const"],
+        "other"
+    );
+}
+
+#[tokio::test]
+async fn retries_failures_on_the_fallback_driver() {
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", MockPdfDriver::new().failing("crashed"));
+    registry.register("backup", MockPdfDriver::new().with_pdf("backup"));
+    registry.set_fallback("chrome", "backup");
+
+    let output = registry.pdf(payload(json!({}))).await.unwrap();
+    assert_eq!(output.pdf, b"backup");
+    assert_eq!(registry.fallbacks_used(), 1);
+}
+
+#[tokio::test]
+async fn names_the_pdf_after_the_filename() {
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", MockPdfDriver::new());
+
+    let output = registry
+        .pdf(payload(json!({ "filename": "Q3 \"report\".pdf" })))
+        .await
+        .unwrap();
+    assert_eq!(
+        output.content_disposition.as_deref(),
+        Some("attachment; filename=\"Q3 _report_.pdf\"")
+    );
+
+    let output = registry
+        .pdf(payload(
+            json!({ "filename": "Übersicht.pdf", "disposition": "inline" }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        output.content_disposition.as_deref(),
+        Some("inline; filename=\"_bersicht.pdf\"; filename*=UTF-8''%C3%9Cbersicht.pdf")
+    );
+}
+
+#[tokio::test]
+async fn fills_in_payloads_from_templates() {
+    let mut registry = DriverRegistry::new();
+    registry.register("chrome", MockPdfDriver::new());
+    let defaults = json!({ "format": "Letter", "landscape": true });
+    registry.set_template("invoice", defaults.as_object().unwrap().clone());
+
+    let resolved = registry
+        .resolve_template(payload(
+            json!({ "template": "invoice", "html": "<p>hi</p>", "landscape": false }),
+        ))
+        .unwrap();
+    assert_eq!(
+        resolved.payload,
+        json!({ "html": "<p>hi</p>", "format": "Letter", "landscape": false })
+    );
+
+    let err = registry
+        .resolve_template(payload(json!({ "template": "missing" })))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<PdfError>(),
+        Some(PdfError::Validation(_))
+    ));
+}
@@ -0,0 +1,53 @@
+//! Typst driver tests, compiled in process with no browser needed.
+
+use pdfan::chrome::PdfDriver;
+use pdfan::error::PdfError;
+use pdfan::typst::{TypstDriver, TypstDriverPdfPayload};
+
+fn payload(content: &str) -> TypstDriverPdfPayload {
+    TypstDriverPdfPayload {
+        content: content.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn compiles_markup_to_pdf() {
+    let output = TypstDriver::new().pdf(payload("= Hello")).await.unwrap();
+
+    assert!(output.pdf.starts_with(b"%PDF-"));
+    let doc = lopdf::Document::load_mem(&output.pdf).unwrap();
+    assert_eq!(doc.get_pages().len(), 1);
+}
+
+#[tokio::test]
+async fn reports_where_compilation_failed() {
+    let err = TypstDriver::new()
+        .pdf(payload("= Hello\n#unknown-function()"))
+        .await
+        .unwrap_err();
+
+    match err.downcast_ref::<PdfError>() {
+        Some(PdfError::BadContent(message)) => {
+            assert!(message.starts_with("2:2: "), "{message}");
+            assert!(message.contains("unknown variable"), "{message}");
+        }
+        _ => panic!("unexpected error: {err:?}"),
+    }
+}
+
+#[tokio::test]
+async fn rejects_content_over_the_limit() {
+    let err = TypstDriver::new()
+        .with_max_content_bytes(4)
+        .pdf(payload("= Hello"))
+        .await
+        .unwrap_err();
+
+    match err.downcast_ref::<PdfError>() {
+        Some(PdfError::Validation(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].field, "content");
+        }
+        _ => panic!("unexpected error: {err:?}"),
+    }
+}
@@ -0,0 +1,24 @@
+//! Payload validation, no browser needed.
+
+use pdfan::chrome::ChromeDriverPdfPayload;
+use pdfan::error::PdfError;
+use serde_json::json;
+
+#[test]
+fn reports_every_violation_at_once() {
+    let payload: ChromeDriverPdfPayload = serde_json::from_value(json!({
+        "html": "<p>hi</p>",
+        "url": "https://example.com",
+        "scale": 5.0,
+        "format": "Napkin",
+        "printRange": "3-x",
+    }))
+    .unwrap();
+
+    let PdfError::Validation(errors) = payload.validate().unwrap_err() else {
+        panic!("expected a validation error");
+    };
+    let mut fields: Vec<_> = errors.iter().map(|error| error.field).collect();
+    fields.sort_unstable();
+    assert_eq!(fields, ["format", "html", "printRange", "scale"]);
+}
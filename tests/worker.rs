@@ -0,0 +1,113 @@
+//! Ordering and robustness tests for the worker pool.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pdfan::worker::{Backpressure, PoolFull, Task, WorkerPool};
+
+struct Record {
+    id: usize,
+    log: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Task<()> for Record {
+    type Result = ();
+
+    async fn process(&self, _ctx: &mut ()) {
+        self.log.lock().unwrap().push(self.id);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn processes_tasks_in_submission_order() {
+    // A small capacity keeps most callers waiting for a slot, where reordering could happen
+    let pool = Arc::new(WorkerPool::new(2, 1, || async { Ok(()) }));
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    let mut handles = Vec::new();
+    for id in 0..20 {
+        let pool = Arc::clone(&pool);
+        let task = Record {
+            id,
+            log: Arc::clone(&log),
+        };
+        handles.push(tokio::spawn(async move {
+            pool.queue(task, Duration::from_secs(10)).await.unwrap();
+        }));
+        // Let the caller get in line before the next one is submitted
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    assert_eq!(*log.lock().unwrap(), (0..20).collect::<Vec<_>>());
+}
+
+struct MaybePanic {
+    panic: bool,
+}
+
+impl Task<()> for MaybePanic {
+    type Result = ();
+
+    async fn process(&self, _ctx: &mut ()) {
+        assert!(!self.panic, "task asked to panic");
+    }
+}
+
+#[tokio::test]
+async fn replaces_a_panicking_worker() {
+    let pool = WorkerPool::new(4, 1, || async { Ok(()) });
+
+    let panicked = pool
+        .queue(MaybePanic { panic: true }, Duration::from_secs(5))
+        .await;
+    assert!(panicked.is_err());
+
+    // The only worker panicked, so this needs its replacement
+    pool.queue(MaybePanic { panic: false }, Duration::from_secs(5))
+        .await
+        .unwrap();
+}
+
+struct Sleep;
+
+impl Task<()> for Sleep {
+    type Result = ();
+
+    async fn process(&self, _ctx: &mut ()) {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn rejects_a_flood_beyond_capacity() {
+    let pool = Arc::new(
+        WorkerPool::new(2, 1, || async { Ok(()) }).with_backpressure(Backpressure::Reject),
+    );
+
+    let handles: Vec<_> = (0..20)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+            tokio::spawn(async move { pool.queue(Sleep, Duration::from_secs(5)).await })
+        })
+        .collect();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let stats = pool.stats();
+    assert!(stats.queued + stats.in_flight <= 2);
+
+    let mut accepted = 0;
+    for handle in handles {
+        match handle.await.unwrap() {
+            Ok(()) => accepted += 1,
+            Err(e) => assert!(
+                e.downcast_ref::<PoolFull>().is_some(),
+                "unexpected error: {e:?}"
+            ),
+        }
+    }
+    assert_eq!(accepted, 2);
+}